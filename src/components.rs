@@ -1,14 +1,17 @@
 use specs::prelude::*;
 use specs_derive::*;
-use rltk::{RGB};
+use specs::saveload::{Marker, ConvertSaveload};
+use specs::error::NoError;
+use serde::{Serialize, Deserialize};
+use rltk::{RGB, RandomNumberGenerator};
 
-#[derive(Component)]
+#[derive(Component, Serialize, Deserialize, Clone)]
 pub struct Position {
     pub x: i32,
     pub y: i32,
 }
 
-#[derive(Component)]
+#[derive(Component, Serialize, Deserialize, Clone)]
 pub struct Renderable {
     pub glyph: rltk::FontCharType,
     pub fg: RGB,
@@ -17,102 +20,387 @@ pub struct Renderable {
 }
 
 // Player stuff
-#[derive(Component, Debug)]
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
 pub struct Player {}
 
-#[derive(Component, Debug)]
+/// The hit point pool and natural weapon, everything else in a fight comes from `Attributes` and `Skills`.
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
 pub struct CombatStats {
     pub max_hp: i32,
     pub hp: i32,
-    pub defense: i32,
-    pub power: i32
+    pub damage: rltk::DiceType
 }
 
-#[derive(Component, Debug, Clone)]
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
+pub struct Attributes {
+    pub might: i32,
+    pub fitness: i32,
+    pub quickness: i32,
+    pub intelligence: i32
+}
+
+#[derive(Component, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Skills {
+    #[serde(default)]
+    pub melee: i32,
+    #[serde(default)]
+    pub dodge: i32,
+    #[serde(default)]
+    pub magic: i32
+}
+
+#[derive(Component, ConvertSaveload, Debug, Clone)]
 pub struct WantsToMelee {
     pub target: Entity,
 }
 
-#[derive(Component, Debug)]
+/// Damage waiting to be applied, along with who dealt it. Never saved, it's applied within the turn it's dealt.
+#[derive(Component, Clone, Debug)]
 pub struct SufferDamage {
-    pub amount: Vec<i32>
+    pub amount: Vec<(i32, Option<Entity>)>
 }
 
 impl SufferDamage {
-    pub fn new_damage(store: &mut WriteStorage<SufferDamage>, victim: Entity, amount: i32) {
+    pub fn new_damage(store: &mut WriteStorage<SufferDamage>, victim: Entity, amount: i32, source: Option<Entity>) {
         if let Some(suffering) = store.get_mut(victim) {
-            suffering.amount.push(amount);
+            suffering.amount.push((amount, source));
         } else {
-            let dmg = SufferDamage { amount: vec![amount]};
+            let dmg = SufferDamage { amount: vec![(amount, source)]};
             store.insert(victim, dmg).expect("Unable to insert damage");
         }
     }
 }
 
-#[derive(Component)]
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
+pub struct Experience {
+    pub level: i32,
+    pub xp: i32
+}
+
+impl Experience {
+    /// How much xp it takes to get from the current level to the next one.
+    pub fn xp_to_next_level(&self) -> i32 {
+        self.level * 100
+    }
+
+    pub fn level_up_pending(&self) -> bool {
+        self.xp >= self.xp_to_next_level()
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Serialize, Deserialize, Debug)]
+pub enum HungerState { WellFed, Normal, Hungry, Starving }
+
+impl HungerState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HungerState::WellFed => "Well Fed",
+            HungerState::Normal => "Normal",
+            HungerState::Hungry => "Hungry",
+            HungerState::Starving => "Starving"
+        }
+    }
+}
+
+/// Counts down the turns left in the current hunger state.
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
+pub struct HungerClock {
+    pub state: HungerState,
+    pub duration: i32
+}
+
+/// How much xp whoever lands the killing blow gets.
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
+pub struct GivesExperience {
+    pub xp: i32
+}
+
+#[derive(Component, Serialize, Deserialize, Clone)]
 pub struct Viewshed {
     pub visible_tiles : Vec<rltk::Point>,
     pub range: i32,
     pub dirty : bool
 }
 
-#[derive(Component, Debug)]
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
 pub struct Monster {}
 
-#[derive(Component, Debug)]
+#[derive(PartialEq, Copy, Clone, Serialize, Deserialize, Debug)]
+pub enum AiMode {
+    Idle,
+    Wander { x: i32, y: i32 },
+    /// Heading for where the player was last seen.
+    Chase { x: i32, y: i32 },
+    Flee,
+    ReturnHome,
+    /// Keeping close to the player, for allies.
+    Follow,
+}
+
+impl AiMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AiMode::Idle => "idle",
+            AiMode::Wander{..} => "wander",
+            AiMode::Chase{..} => "chase",
+            AiMode::Flee => "flee",
+            AiMode::ReturnHome => "home",
+            AiMode::Follow => "follow",
+        }
+    }
+
+    /// Where this mode is trying to get to, if anywhere in particular.
+    pub fn destination(&self) -> Option<(i32, i32)> {
+        match self {
+            AiMode::Wander{ x, y } | AiMode::Chase{ x, y } => Some((*x, *y)),
+            _ => None,
+        }
+    }
+}
+
+/// Built up by `speed` every turn and spent on actions, so faster things act more often.
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
+pub struct Energy {
+    pub speed: i32,
+    pub energy: i32
+}
+
+/// Given to a monster by the scheduler when it has the energy to act this round.
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
+pub struct MyTurn {}
+
+/// Attacking with this weapon takes `cost` energy rather than a normal action's worth.
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
+pub struct AttackCost {
+    pub cost: i32
+}
+
+/// Fights alongside the player and follows them down to each new level.
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
+pub struct Ally {}
+
+/// Which side something is on; how the sides get along is in the raws' faction table.
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
+pub struct Faction {
+    pub name: String
+}
+
+/// What a monster is up to, and the spot it wanders around and goes back to.
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
+pub struct Brain {
+    pub mode: AiMode,
+    pub home_x: i32,
+    pub home_y: i32
+}
+
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
 pub struct Name {
     pub name: String,
 }
 
-#[derive(Component, Debug)]
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
 pub struct BlocksTile {}
 
 
 //items
-#[derive( Component, Debug)]
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
 pub struct Item {}
 
-#[derive(Component, Debug, Clone)]
+#[derive(Component, ConvertSaveload, Debug, Clone)]
 pub struct InBackpack {
     pub owner: Entity
 }
 
-#[derive(Component, Debug, Clone)]
+#[derive(Component, ConvertSaveload, Debug, Clone)]
 pub struct WantsToPickupItem {
     pub collected_by: Entity,
     pub item: Entity
 }
 
-#[derive(Component, Debug)]
+#[derive(Component, ConvertSaveload, Debug, Clone)]
 pub struct WantsToUse {
     pub item: Entity,
     pub target : Option<rltk::Point>
 }
 
-#[derive(Component, Debug, Clone)]
+#[derive(Component, ConvertSaveload, Debug, Clone)]
 pub struct WantsToDrop {
     pub item: Entity
 }
 
-#[derive(Component)]
+#[derive(Component, Serialize, Deserialize, Clone)]
 pub struct Consumable {}
 
-#[derive(Component, Debug)]
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
 pub struct ProvidesHealing {
     pub heal_amount: i32
 }
 
-#[derive(Component, Debug)]
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
+pub struct ProvidesFood {}
+
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
 pub struct Ranged {
     pub range: i32
 }
 
-#[derive(Component, Debug)]
+/// An item a monster knows rather than carries: it's never used up, and the monster
+/// tries it with `chance` percent odds each turn the player is in sight and in range.
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
+pub struct Spell {
+    pub chance: i32
+}
+
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
 pub struct InflictDamage {
     pub damage: i32
 }
 
-#[derive(Component, Debug)]
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
 pub struct AreaOfEffect {
     pub radius: i32
-}
\ No newline at end of file
+}
+
+// status effects
+#[derive(PartialEq, Copy, Clone, Serialize, Deserialize, Debug)]
+pub enum StatusEffectKind {
+    Confusion,
+    Poison { damage: i32 },
+    Regeneration { amount: i32 }
+}
+
+impl StatusEffectKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            StatusEffectKind::Confusion => "Confused",
+            StatusEffectKind::Poison{..} => "Poisoned",
+            StatusEffectKind::Regeneration{..} => "Regenerating"
+        }
+    }
+}
+
+/// Lives on its own entity and wears off after `turns` turns.
+/// `source` is whoever gets the credit for what it does, if they're still around.
+#[derive(Component, Debug, Clone)]
+pub struct StatusEffect {
+    pub target: Entity,
+    pub source: Option<Entity>,
+    pub kind: StatusEffectKind,
+    pub turns: i32
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StatusEffectData<M> {
+    target: M,
+    source: Option<M>,
+    kind: StatusEffectKind,
+    turns: i32
+}
+
+// written out by hand since the derive has no way to save an `Option<Entity>`
+impl<M: Marker + Serialize + serde::de::DeserializeOwned> ConvertSaveload<M> for StatusEffect {
+    type Data = StatusEffectData<M>;
+    type Error = NoError;
+
+    fn convert_into<F>(&self, mut ids: F) -> Result<Self::Data, Self::Error> where F: FnMut(Entity) -> Option<M> {
+        let target = ids(self.target).expect("Status effect on an entity that isn't being saved");
+        // a source that's been killed since has no marker, and simply isn't saved
+        let source = self.source.and_then(&mut ids);
+        Ok(StatusEffectData { target, source, kind: self.kind, turns: self.turns })
+    }
+
+    fn convert_from<F>(data: Self::Data, mut ids: F) -> Result<Self, Self::Error> where F: FnMut(M) -> Option<Entity> {
+        let target = ids(data.target).expect("Status effect on an entity that wasn't loaded");
+        let source = data.source.and_then(&mut ids);
+        Ok(StatusEffect { target, source, kind: data.kind, turns: data.turns })
+    }
+}
+
+/// Items that put a status effect on whoever they're used on.
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
+pub struct InflictsStatus {
+    pub kind: StatusEffectKind,
+    pub turns: i32
+}
+
+// traps
+/// Not drawn or tooltipped until it's been found.
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
+pub struct Hidden {}
+
+/// Goes off when something steps onto its tile.
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
+pub struct EntryTrigger {}
+
+/// Marks something that moved this turn, so triggers on its new tile can fire.
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
+pub struct EntityMoved {}
+
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
+pub struct SingleActivation {}
+
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
+pub struct TeleportsVictim {}
+
+/// Alerts every monster within `radius` to where it went off.
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
+pub struct Alarm {
+    pub radius: i32
+}
+
+/// A monster that heard something at `(x, y)` and is going to look.
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
+pub struct Alerted {
+    pub x: i32,
+    pub y: i32
+}
+
+// doors
+/// Stops line of sight through its tile.
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
+pub struct BlocksVisibility {}
+
+/// Blocks its tile while closed, bumping into it opens it.
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
+pub struct Door {
+    pub open: bool
+}
+
+// equipment
+#[derive(PartialEq, Copy, Clone, Serialize, Deserialize, Debug)]
+pub enum EquipmentSlot { Melee, Shield, Armor }
+
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
+pub struct Equippable {
+    pub slot: EquipmentSlot
+}
+
+#[derive(Component, ConvertSaveload, Debug, Clone)]
+pub struct Equipped {
+    pub owner: Entity,
+    pub slot: EquipmentSlot
+}
+
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
+pub struct MeleePowerBonus {
+    pub power: i32
+}
+
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
+pub struct DefenseBonus {
+    pub defense: i32
+}
+
+// Serialization helpers
+pub struct SerializeMe;
+
+/// Carries the resources (map, log, seed and rng) through a save file, since only components get serialized.
+#[derive(Component, Serialize, Deserialize, Clone)]
+pub struct SerializationHelper {
+    pub map: super::map::Map,
+    pub log: Vec<String>,
+    pub seed: u64,
+    pub rng: RandomNumberGenerator,
+    pub replay: Vec<super::replay::RecordedAction>,
+    pub builder: Option<super::map_builders::BuilderKind>
+}
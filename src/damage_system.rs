@@ -1,20 +1,40 @@
 use specs::prelude::*;
-use super::{CombatStats,SufferDamage, Player, gamelog::GameLog, Name};
-use rltk::console;
+use super::{CombatStats,SufferDamage, Player, Name, RunState, Experience, GivesExperience, InBackpack, events::{EventQueue, GameEvent}};
 
 pub struct DamageSystem {}
 
 impl<'a> System<'a> for DamageSystem {
+    #[allow(clippy::type_complexity)]
     type SystemData = (WriteStorage<'a, CombatStats>,
-                        WriteStorage<'a, SufferDamage>);
+                        WriteStorage<'a, SufferDamage>,
+                        WriteExpect<'a, EventQueue>,
+                        WriteStorage<'a, Experience>,
+                        ReadStorage<'a, GivesExperience>);
 
     fn run (&mut self, data: Self::SystemData) {
-        let (mut stats, mut damage) = data;
+        let (mut stats, mut damage, mut events, mut experience, gives_experience) = data;
 
-        for (mut stats, damage) in (&mut stats, &damage).join() {
-            stats.hp -= damage.amount.iter().sum::<i32>();
+        // whoever takes a victim from alive to dead gets its xp
+        let mut xp_awards: Vec<(Entity, i32)> = Vec::new();
+        for (mut stats, damage, reward) in (&mut stats, &damage, gives_experience.maybe()).join() {
+            for (amount, source) in damage.amount.iter() {
+                let was_alive = stats.hp > 0;
+                stats.hp -= amount;
+                if was_alive && stats.hp < 1 {
+                    if let (Some(killer), Some(reward)) = (source, reward) {
+                        xp_awards.push((*killer, reward.xp));
+                    }
+                }
+            }
+        }
+        damage.clear();
+
+        for (killer, xp) in xp_awards.iter() {
+            if let Some(exp) = experience.get_mut(*killer) {
+                exp.xp += xp;
+                events.emit(GameEvent::XpGained { entity: *killer, amount: *xp });
+            }
         }
-        damage.clear()
     }
 
 }
@@ -28,26 +48,91 @@ impl DamageSystem {
             let players = ecs.read_storage::<Player>();
             let names = ecs.read_storage::<Name>();
             let entities = ecs.entities();
-            let mut log = ecs.write_resource::<GameLog>();
+            let mut events = ecs.write_resource::<EventQueue>();
             for (entity, stats) in (&entities, &combat_stats).join() {
                 if stats.hp < 1 {
+                    let name = names.get(entity).map(|name| name.name.clone()).unwrap_or_else(|| "Something".to_string());
                     let player = players.get(entity);
                     match player {
                         None => {
-                            let victim_name = names.get(entity);
-                            if let Some(victim_name) = victim_name {
-                                log.entries.push(format!("{} is dead", &victim_name.name));
-                            }
+                            events.emit(GameEvent::EntityDied { entity, name });
                             dead.push(entity)
                         },
-                        Some(_) => console::log("You are dead")
+                        Some(_) => {
+                            let mut runstate = ecs.write_resource::<RunState>();
+                            if *runstate != RunState::GameOver {
+                                events.emit(GameEvent::EntityDied { entity, name });
+                            }
+                            *runstate = RunState::GameOver;
+                        }
                     }
                 }
             }
         }
 
+        // whatever a dead monster was carrying, like its spells, goes with it
+        {
+            let entities = ecs.entities();
+            let backpack = ecs.read_storage::<InBackpack>();
+            let mut carried: Vec<Entity> = Vec::new();
+            for (item, pack) in (&entities, &backpack).join() {
+                if dead.contains(&pack.owner) {
+                    carried.push(item);
+                }
+            }
+            dead.extend(carried);
+        }
+
         for victim in dead {
             ecs.delete_entity(victim).expect("Unable to delete");
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{State, raws};
+
+    fn hurt(gs: &mut State, victim: Entity, amount: i32, source: Option<Entity>) {
+        let mut damage = gs.ecs.write_storage::<SufferDamage>();
+        SufferDamage::new_damage(&mut damage, victim, amount, source);
+    }
+
+    fn xp_of(gs: &State, entity: Entity) -> i32 {
+        gs.ecs.read_storage::<Experience>().get(entity).unwrap().xp
+    }
+
+    #[test]
+    fn the_killing_blow_earns_the_xp_once() {
+        let mut gs = State::new_game(1, None, false, raws::load_raws().unwrap());
+        let player = *gs.ecs.fetch::<Entity>();
+        let goblin = raws::spawn_named_entity(&mut gs.ecs, "Goblin", 1, 1).unwrap();
+        let hp = gs.ecs.read_storage::<CombatStats>().get(goblin).unwrap().hp;
+        gs.ecs.write_resource::<EventQueue>().clear();
+
+        hurt(&mut gs, goblin, hp - 1, Some(player));
+        DamageSystem{}.run_now(&gs.ecs);
+        assert_eq!(xp_of(&gs, player), 0);
+
+        // the second hit is overkill on an already dead goblin and earns nothing more
+        hurt(&mut gs, goblin, 1, Some(player));
+        hurt(&mut gs, goblin, 5, Some(player));
+        DamageSystem{}.run_now(&gs.ecs);
+        assert_eq!(xp_of(&gs, player), 25);
+        let gains: Vec<i32> = gs.ecs.fetch::<EventQueue>().iter().filter_map(|event| match event {
+            GameEvent::XpGained { entity, amount } if *entity == player => Some(*amount),
+            _ => None
+        }).collect();
+        assert_eq!(gains, vec![25]);
+    }
+
+    #[test]
+    fn deaths_nobody_caused_give_no_xp() {
+        let mut gs = State::new_game(1, None, false, raws::load_raws().unwrap());
+        let player = *gs.ecs.fetch::<Entity>();
+        let goblin = raws::spawn_named_entity(&mut gs.ecs, "Goblin", 1, 1).unwrap();
+        hurt(&mut gs, goblin, 100, None);
+        DamageSystem{}.run_now(&gs.ecs);
+        assert_eq!(xp_of(&gs, player), 0);
+    }
+}
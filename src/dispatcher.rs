@@ -0,0 +1,159 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use specs::prelude::*;
+use super::{VisibilitySystem, SearchSystem, EnergySystem, MonsterAI, StatusEffectSystem, HungerSystem, MapIndexingSystem, TriggerSystem, DamageSystem, MeleeCombatSystem};
+use super::inventory_system::{InventoryCollectionSystem, ItemUseSystem, ItemDropSystem};
+use super::events::EventLogSystem;
+
+/// How long each system took the last time it ran, in the order they were added.
+/// Shared with every `Timed` wrapper so it can be read without going through the `World`.
+#[derive(Clone, Default)]
+pub struct SystemTimings {
+    entries: Arc<Mutex<Vec<(&'static str, Duration)>>>,
+}
+
+impl SystemTimings {
+    fn slot(&self, name: &'static str) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        entries.push((name, Duration::default()));
+        entries.len() - 1
+    }
+
+    fn record(&self, slot: usize, elapsed: Duration) {
+        self.entries.lock().unwrap()[slot].1 = elapsed;
+    }
+
+    pub fn snapshot(&self) -> Vec<(&'static str, Duration)> {
+        self.entries.lock().unwrap().clone()
+    }
+}
+
+/// Runs the wrapped system and notes how long it took.
+struct Timed<S> {
+    system: S,
+    slot: usize,
+    timings: SystemTimings,
+}
+
+impl<'a, S: System<'a>> System<'a> for Timed<S> {
+    type SystemData = S::SystemData;
+
+    // there's no clock to read on the web, so the timings just stay at zero there
+    #[cfg(not(target_arch = "wasm32"))]
+    fn run(&mut self, data: Self::SystemData) {
+        let start = std::time::Instant::now();
+        self.system.run(data);
+        self.timings.record(self.slot, start.elapsed());
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn run(&mut self, data: Self::SystemData) {
+        self.system.run(data);
+    }
+}
+
+/// Adds systems to a `DispatcherBuilder`, each wrapped so its run time ends up in `timings`.
+struct TimedBuilder {
+    builder: DispatcherBuilder<'static, 'static>,
+    timings: SystemTimings,
+}
+
+impl TimedBuilder {
+    fn with<S>(mut self, system: S, name: &'static str, dependencies: &[&str]) -> Self
+    where S: for<'c> System<'c> + Send + 'static {
+        let slot = self.timings.slot(name);
+        let timed = Timed { system, slot, timings: self.timings.clone() };
+        self.builder = self.builder.with(timed, name, dependencies);
+        self
+    }
+}
+
+/// Builds the dispatcher that runs one scheduling round. The dependencies pin down the order
+/// things have to happen in; systems that don't depend on each other and don't touch the same
+/// data are free to run side by side when dispatched in parallel.
+pub fn build_dispatcher(timings: &SystemTimings) -> Dispatcher<'static, 'static> {
+    let builder = TimedBuilder { builder: DispatcherBuilder::new(), timings: timings.clone() }
+        .with(VisibilitySystem{}, "visibility", &[])
+        // the AI reads who stands where, so the index is brought up to date first
+        .with(MapIndexingSystem{}, "map_indexing", &[])
+        .with(SearchSystem{}, "search", &["visibility"])
+        .with(EnergySystem{}, "energy", &[])
+        .with(MonsterAI{}, "monster_ai", &["visibility", "map_indexing", "energy"])
+        // status effects and hunger tick once a turn, after whoever got to act at the start of it
+        .with(StatusEffectSystem{}, "status_effects", &["energy", "monster_ai"])
+        .with(HungerSystem{}, "hunger", &["energy", "status_effects"])
+        // traps go off for anything that moved this round
+        .with(TriggerSystem{}, "triggers", &["map_indexing", "search", "monster_ai"])
+        .with(MeleeCombatSystem{}, "melee", &["monster_ai"])
+        .with(InventoryCollectionSystem{}, "pickup", &["monster_ai"])
+        .with(ItemUseSystem{}, "item_use", &["monster_ai", "pickup"])
+        .with(ItemDropSystem{}, "drop", &["item_use"])
+        // everything that hurts has queued its damage by now
+        .with(DamageSystem{}, "damage", &["status_effects", "hunger", "triggers", "melee", "item_use"])
+        // and again once everything has moved, so the player's next bump or aim sees where things are now
+        .with(MapIndexingSystem{}, "map_reindex", &["triggers", "drop", "damage"]);
+
+    builder.builder.build()
+}
+
+/// Builds the dispatcher for everything that reacts to the round's events, run once the
+/// round is over and the dead have been cleared away.
+pub fn build_subscribers(timings: &SystemTimings) -> Dispatcher<'static, 'static> {
+    let builder = TimedBuilder { builder: DispatcherBuilder::new(), timings: timings.clone() }
+        .with(EventLogSystem{}, "event_log", &[]);
+
+    builder.builder.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{State, Map, Position, CombatStats, SufferDamage, InBackpack, WantsToUse, WantsToDrop, raws};
+
+    /// Queues a damaging item use and a drop, runs a single round and checks that the damage
+    /// landed and the dropped item is indexed, both of which need the systems in the right order.
+    fn check_one_round(parallel: bool) {
+        let mut gs = State::new_game(1, None, false, raws::load_raws().unwrap());
+        let player = *gs.ecs.fetch::<Entity>();
+        let player_pos = *gs.ecs.fetch::<rltk::Point>();
+        let goblin = raws::spawn_named_entity(&mut gs.ecs, "Goblin", player_pos.x + 1, player_pos.y).unwrap();
+        let scroll = raws::spawn_named_entity(&mut gs.ecs, "Scroll of Magic Missile", 0, 0).unwrap();
+        let potion = raws::spawn_named_entity(&mut gs.ecs, "Health Potion", 0, 0).unwrap();
+        for item in [scroll, potion] {
+            gs.ecs.write_storage::<Position>().remove(item);
+            gs.ecs.write_storage::<InBackpack>().insert(item, InBackpack { owner: player }).unwrap();
+        }
+        let target = Some(rltk::Point::new(player_pos.x + 1, player_pos.y));
+        gs.ecs.write_storage::<WantsToUse>().insert(player, WantsToUse { item: scroll, target }).unwrap();
+        gs.ecs.write_storage::<WantsToDrop>().insert(player, WantsToDrop { item: potion }).unwrap();
+
+        let mut dispatcher = build_dispatcher(&SystemTimings::default());
+        if parallel { dispatcher.dispatch_par(&gs.ecs); } else { dispatcher.dispatch_seq(&gs.ecs); }
+
+        // the goblin was indexed before the scroll looked for it, and the damage system ran after it hit
+        assert!(gs.ecs.read_storage::<CombatStats>().get(goblin).unwrap().hp <= 0);
+        assert!(gs.ecs.read_storage::<SufferDamage>().get(goblin).is_none());
+        // the map was indexed again after the drop
+        let map = gs.ecs.fetch::<Map>();
+        assert!(map.tile_content[map.xy_idx(player_pos.x, player_pos.y)].contains(&potion));
+    }
+
+    #[test]
+    fn one_round_in_order() {
+        check_one_round(false);
+    }
+
+    #[test]
+    fn one_round_in_order_in_parallel() {
+        check_one_round(true);
+    }
+
+    #[test]
+    fn timings_list_every_system() {
+        let timings = SystemTimings::default();
+        let _systems = build_dispatcher(&timings);
+        let names: Vec<&str> = timings.snapshot().iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, vec!["visibility", "map_indexing", "search", "energy", "monster_ai", "status_effects", "hunger",
+            "triggers", "melee", "pickup", "item_use", "drop", "damage", "map_reindex"]);
+    }
+}
@@ -0,0 +1,108 @@
+use specs::prelude::*;
+use super::{Energy, MyTurn, Monster, Brain, RunState};
+
+/// What a normal action costs, and what something has to have saved up before it can act.
+pub const ACTION_COST: i32 = 100;
+
+/// How many scheduling rounds can pass waiting on the player before giving up and handing
+/// control back anyway, so a player that never gains energy can't hang the game.
+pub const MAX_ROUNDS_PER_TURN: usize = 1000;
+
+/// Whether the last scheduling round started a new turn, for anything that ticks once a turn.
+/// It isn't saved: `EnergySystem` sets it afresh at the start of every round, before anything
+/// reads it, so a loaded game loses nothing by starting from the default.
+#[derive(Default)]
+pub struct TurnClock {
+    pub new_turn: bool,
+}
+
+/// The scheduler. When nobody has enough energy to act, a new turn starts and everything
+/// gains its speed in energy; every monster that can afford an action then gets `MyTurn`.
+/// Anything else with energy besides the player has nothing to act with, and just passes.
+pub struct EnergySystem {}
+
+impl<'a> System<'a> for EnergySystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadExpect<'a, Entity>,
+        ReadExpect<'a, RunState>,
+        WriteExpect<'a, TurnClock>,
+        WriteStorage<'a, Energy>,
+        ReadStorage<'a, Monster>,
+        ReadStorage<'a, Brain>,
+        WriteStorage<'a, MyTurn>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (entities, player_entity, runstate, mut clock, mut energies, monsters, brains, mut my_turn) = data;
+
+        clock.new_turn = false;
+        if *runstate != RunState::Ticking {return;}
+
+        if !energies.join().any(|energy| energy.energy >= ACTION_COST) {
+            clock.new_turn = true;
+            for energy in (&mut energies).join() {
+                energy.energy += energy.speed;
+            }
+        }
+
+        for (entity, energy) in (&entities, &mut energies).join() {
+            if energy.energy < ACTION_COST || entity == *player_entity { continue; }
+            if monsters.get(entity).is_some() && brains.get(entity).is_some() {
+                my_turn.insert(entity, MyTurn{}).expect("Unable to insert turn");
+            } else {
+                // left holding the energy, it would stop new turns from ever starting
+                energy.energy -= ACTION_COST;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{State, MonsterAI, raws};
+
+    #[test]
+    fn faster_monsters_get_more_turns() {
+        let mut gs = State::new_game(1, None, false, raws::load_raws().unwrap());
+        // only the two being compared take part, anything else holding energy would stall the turns
+        gs.ecs.write_storage::<Energy>().clear();
+        let goblin = raws::spawn_named_entity(&mut gs.ecs, "Goblin", 1, 1).unwrap();
+        let beetle = raws::spawn_named_entity(&mut gs.ecs, "Giant Beetle", 2, 1).unwrap();
+        *gs.ecs.write_resource::<RunState>() = RunState::Ticking;
+
+        let (mut goblin_turns, mut beetle_turns) = (0, 0);
+        for _ in 0..200 {
+            EnergySystem{}.run_now(&gs.ecs);
+            let entities = gs.ecs.entities();
+            let mut energies = gs.ecs.write_storage::<Energy>();
+            let mut my_turn = gs.ecs.write_storage::<MyTurn>();
+            for (entity, energy, _turn) in (&entities, &mut energies, &my_turn).join() {
+                energy.energy -= ACTION_COST;
+                if entity == goblin { goblin_turns += 1; }
+                if entity == beetle { beetle_turns += 1; }
+            }
+            my_turn.clear();
+        }
+
+        assert!(goblin_turns > beetle_turns, "goblin {} beetle {}", goblin_turns, beetle_turns);
+        assert!(beetle_turns > 0);
+    }
+
+    #[test]
+    fn something_without_a_brain_doesnt_hold_up_the_turns() {
+        let mut gs = State::new_game(1, None, false, raws::load_raws().unwrap());
+        gs.ecs.write_storage::<Energy>().clear();
+        gs.ecs.create_entity().with(Monster{}).with(Energy { speed: 100, energy: 0 }).build();
+        *gs.ecs.write_resource::<RunState>() = RunState::Ticking;
+
+        let mut new_turns = 0;
+        for _ in 0..50 {
+            EnergySystem{}.run_now(&gs.ecs);
+            MonsterAI{}.run_now(&gs.ecs);
+            if gs.ecs.fetch::<TurnClock>().new_turn { new_turns += 1; }
+        }
+        assert!(new_turns >= 20, "only {} new turns", new_turns);
+    }
+}
@@ -0,0 +1,269 @@
+use specs::prelude::*;
+use super::{StatusEffectKind, HungerState, gamelog::GameLog};
+
+/// What did the damage in a `DamageDealt`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DamageKind {
+    Melee,
+    Critical,
+    Item { item: String },
+    Poison,
+    Trap,
+    Starvation,
+}
+
+/// One thing an item did when it was used.
+#[derive(Clone, Debug, PartialEq)]
+pub enum UseOutcome {
+    Equipped,
+    /// Taken off, either on purpose or to make room for what was just equipped.
+    Unequipped { item: String },
+    Eaten { target: Entity },
+    Healed { target: Entity, amount: i32 },
+    StatusApplied { target: Entity, target_name: String, kind: StatusEffectKind },
+}
+
+/// Something that happened during a round. Names are copied in since the entities involved
+/// may well be deleted by the time anything reads the event.
+#[derive(Clone, Debug)]
+pub enum GameEvent {
+    DamageDealt { source: Option<Entity>, source_name: String, target: Entity, target_name: String, amount: i32, kind: DamageKind },
+    AttackMissed { attacker: Entity, attacker_name: String, target: Entity, target_name: String, fumble: bool },
+    EntityDied { entity: Entity, name: String },
+    XpGained { entity: Entity, amount: i32 },
+    ItemPickedUp { by: Entity, item: Entity, name: String },
+    ItemDropped { by: Entity, item: Entity, name: String },
+    ItemUsed { user: Entity, user_name: String, item: Entity, name: String, outcomes: Vec<UseOutcome> },
+    StatusExpired { target: Entity, target_name: String, kind: StatusEffectKind },
+    HungerChanged { entity: Entity, state: HungerState },
+    /// `seen` is whether the player could see it happen.
+    TrapTriggered { trap: Entity, trap_name: String, victim: Entity, victim_name: String, seen: bool },
+    Teleported { entity: Entity },
+    AlarmRaised { x: i32, y: i32 },
+    Spotted { entity: Entity, name: String },
+    LevelEntered { depth: i32 },
+}
+
+/// Events emitted since the subscribers last ran. Systems push to it during a round, the
+/// subscribers read it once the round is over and then it's cleared for the next one.
+#[derive(Default)]
+pub struct EventQueue {
+    events: Vec<GameEvent>,
+}
+
+impl EventQueue {
+    pub fn emit(&mut self, event: GameEvent) {
+        self.events.push(event);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &GameEvent> {
+        self.events.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+}
+
+/// Writes the events the player should hear about into the game log.
+pub struct EventLogSystem {}
+
+impl<'a> System<'a> for EventLogSystem {
+    type SystemData = (
+        ReadExpect<'a, EventQueue>,
+        ReadExpect<'a, Entity>,
+        WriteExpect<'a, GameLog>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (events, player_entity, mut log) = data;
+        let player = *player_entity;
+
+        for event in events.iter() {
+            match event {
+                GameEvent::DamageDealt { source, source_name, target, target_name, amount, kind } => {
+                    let message = match kind {
+                        DamageKind::Melee => Some(format!("{} hits {} for {} hp", source_name, target_name, amount)),
+                        DamageKind::Critical => Some(format!("{} lands a critical hit on {} for {} hp!", source_name, target_name, amount)),
+                        DamageKind::Item { item } if *source == Some(player) => Some(format!("You use {} on {}, inflicting {} hp.", item, target_name, amount)),
+                        DamageKind::Item { item } if *target == player => Some(format!("{} uses {} on you, inflicting {} hp.", source_name, item, amount)),
+                        DamageKind::Poison if *target == player => Some(format!("The poison burns for {} hp.", amount)),
+                        _ => None
+                    };
+                    if let Some(message) = message {
+                        log.entries.push(message);
+                    }
+                }
+                GameEvent::AttackMissed { attacker_name, target_name, fumble: true, .. } => {
+                    log.entries.push(format!("{} fumbles the attack on {}", attacker_name, target_name));
+                }
+                GameEvent::AttackMissed { attacker_name, target_name, fumble: false, .. } => {
+                    log.entries.push(format!("{} attacks {}, but misses", attacker_name, target_name));
+                }
+                GameEvent::EntityDied { entity, .. } if *entity == player => {
+                    log.entries.push("You are dead".to_string());
+                }
+                GameEvent::EntityDied { name, .. } => {
+                    log.entries.push(format!("{} is dead", name));
+                }
+                GameEvent::XpGained { entity, amount } if *entity == player => {
+                    log.entries.push(format!("You gain {} xp.", amount));
+                }
+                GameEvent::ItemPickedUp { by, name, .. } if *by == player => {
+                    log.entries.push(format!("You pick up the {}", name));
+                }
+                GameEvent::ItemDropped { by, name, .. } if *by == player => {
+                    log.entries.push(format!("You drop the {}", name));
+                }
+                GameEvent::ItemUsed { user, user_name, name, outcomes, .. } => {
+                    for outcome in outcomes.iter() {
+                        let message = match outcome {
+                            UseOutcome::Unequipped { item } if *user == player => Some(format!("You unequip {}.", item)),
+                            UseOutcome::Equipped if *user == player => Some(format!("You equip {}.", name)),
+                            UseOutcome::Eaten { target } if *target == player => Some(format!("You eat the {}.", name)),
+                            UseOutcome::Healed { amount, .. } if *user == player => Some(format!("You use the {}, healing {} hp", name, amount)),
+                            UseOutcome::StatusApplied { target_name, kind, .. } if *user == player =>
+                                Some(format!("You use {} on {}, leaving it {}.", name, target_name, kind.name().to_lowercase())),
+                            UseOutcome::StatusApplied { target, kind, .. } if *target == player =>
+                                Some(format!("{} uses {} on you, leaving you {}.", user_name, name, kind.name().to_lowercase())),
+                            _ => None
+                        };
+                        if let Some(message) = message {
+                            log.entries.push(message);
+                        }
+                    }
+                }
+                GameEvent::StatusExpired { target, kind, .. } if *target == player => {
+                    log.entries.push(format!("You are no longer {}.", kind.name().to_lowercase()));
+                }
+                GameEvent::StatusExpired { target_name, kind, .. } if !target_name.is_empty() => {
+                    log.entries.push(format!("{} is no longer {}.", target_name, kind.name().to_lowercase()));
+                }
+                GameEvent::HungerChanged { entity, state } if *entity == player => {
+                    let message = match state {
+                        HungerState::Normal => Some("You are no longer well fed."),
+                        HungerState::Hungry => Some("You are hungry."),
+                        HungerState::Starving => Some("You are starving! Your hunger pangs are getting painful."),
+                        HungerState::WellFed => None
+                    };
+                    if let Some(message) = message {
+                        log.entries.push(message.to_string());
+                    }
+                }
+                GameEvent::TrapTriggered { trap_name, victim, .. } if *victim == player => {
+                    log.entries.push(format!("You trigger the {}!", trap_name));
+                }
+                GameEvent::TrapTriggered { trap_name, victim_name, seen: true, .. } if !victim_name.is_empty() => {
+                    log.entries.push(format!("{} triggers the {}!", victim_name, trap_name));
+                }
+                GameEvent::Teleported { entity } if *entity == player => {
+                    log.entries.push("The world spins around you!".to_string());
+                }
+                GameEvent::AlarmRaised { .. } => {
+                    log.entries.push("A loud alarm rings out!".to_string());
+                }
+                GameEvent::Spotted { name, .. } => {
+                    log.entries.push(format!("You spot a {}.", name));
+                }
+                GameEvent::LevelEntered { depth } => {
+                    log.entries.push(format!("You descend to depth {}, and take a moment to heal.", depth));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs the log subscriber over the events `make` builds for a player and a monster, and hands back what it wrote.
+    fn log_of<F>(make: F) -> Vec<String> where F: FnOnce(Entity, Entity) -> Vec<GameEvent> {
+        let mut world = World::new();
+        let player = world.create_entity().build();
+        let monster = world.create_entity().build();
+        let mut queue = EventQueue::default();
+        for event in make(player, monster) {
+            queue.emit(event);
+        }
+        world.insert(queue);
+        world.insert(player);
+        world.insert(GameLog { entries: Vec::new() });
+
+        EventLogSystem{}.run_now(&world);
+        let entries = world.fetch::<GameLog>().entries.clone();
+        entries
+    }
+
+    fn name(name: &str) -> String {
+        name.to_string()
+    }
+
+    #[test]
+    fn events_are_kept_until_cleared() {
+        let player = World::new().create_entity().build();
+        let mut queue = EventQueue::default();
+        queue.emit(GameEvent::LevelEntered { depth: 2 });
+        queue.emit(GameEvent::XpGained { entity: player, amount: 5 });
+        assert_eq!(queue.iter().count(), 2);
+        queue.clear();
+        assert_eq!(queue.iter().count(), 0);
+    }
+
+    #[test]
+    fn the_log_reports_from_the_players_side() {
+        let log = log_of(|player, monster| vec![
+            GameEvent::DamageDealt { source: Some(player), source_name: name("Player"), target: monster, target_name: name("Orc"), amount: 3, kind: DamageKind::Melee },
+            GameEvent::DamageDealt { source: Some(monster), source_name: name("Orc"), target: player, target_name: name("Player"), amount: 9, kind: DamageKind::Critical },
+            GameEvent::AttackMissed { attacker: monster, attacker_name: name("Orc"), target: player, target_name: name("Player"), fumble: true },
+            GameEvent::DamageDealt { source: Some(player), source_name: name("Player"), target: monster, target_name: name("Orc"), amount: 8, kind: DamageKind::Item { item: name("Magic Missile") } },
+            GameEvent::DamageDealt { source: None, source_name: name("Orc Shaman"), target: monster, target_name: name("Orc"), amount: 1, kind: DamageKind::Poison },
+            GameEvent::DamageDealt { source: None, source_name: name("Orc Shaman"), target: player, target_name: name("Player"), amount: 1, kind: DamageKind::Poison },
+            GameEvent::EntityDied { entity: monster, name: name("Orc") },
+            GameEvent::XpGained { entity: player, amount: 10 },
+            GameEvent::ItemDropped { by: monster, item: monster, name: name("Dagger") },
+            GameEvent::ItemDropped { by: player, item: monster, name: name("Dagger") },
+            GameEvent::LevelEntered { depth: 3 },
+        ]);
+        assert_eq!(log, vec![
+            "Player hits Orc for 3 hp",
+            "Orc lands a critical hit on Player for 9 hp!",
+            "Orc fumbles the attack on Player",
+            "You use Magic Missile on Orc, inflicting 8 hp.",
+            "The poison burns for 1 hp.",
+            "Orc is dead",
+            "You gain 10 xp.",
+            "You drop the Dagger",
+            "You descend to depth 3, and take a moment to heal.",
+        ]);
+    }
+
+    #[test]
+    fn the_log_only_mentions_what_the_player_would_know() {
+        let log = log_of(|player, monster| vec![
+            GameEvent::TrapTriggered { trap: monster, trap_name: name("Bear Trap"), victim: monster, victim_name: name("Goblin"), seen: false },
+            GameEvent::TrapTriggered { trap: monster, trap_name: name("Bear Trap"), victim: monster, victim_name: name("Goblin"), seen: true },
+            GameEvent::TrapTriggered { trap: monster, trap_name: name("Teleport Trap"), victim: player, victim_name: name("Player"), seen: true },
+            GameEvent::Teleported { entity: monster },
+            GameEvent::Teleported { entity: player },
+            GameEvent::HungerChanged { entity: monster, state: HungerState::Hungry },
+            GameEvent::HungerChanged { entity: player, state: HungerState::Hungry },
+            GameEvent::StatusExpired { target: player, target_name: name("Player"), kind: StatusEffectKind::Confusion },
+            GameEvent::StatusExpired { target: monster, target_name: name("Goblin"), kind: StatusEffectKind::Confusion },
+            GameEvent::ItemUsed { user: player, user_name: name("Player"), item: monster, name: name("Rations"), outcomes: vec![UseOutcome::Eaten { target: player }] },
+            GameEvent::ItemUsed { user: monster, user_name: name("Goblin"), item: monster, name: name("Confusion"),
+                outcomes: vec![UseOutcome::StatusApplied { target: player, target_name: name("Player"), kind: StatusEffectKind::Confusion }] },
+        ]);
+        assert_eq!(log, vec![
+            "Goblin triggers the Bear Trap!",
+            "You trigger the Teleport Trap!",
+            "The world spins around you!",
+            "You are hungry.",
+            "You are no longer confused.",
+            "Goblin is no longer confused.",
+            "You eat the Rations.",
+            "Goblin uses Confusion on you, leaving you confused.",
+        ]);
+    }
+}
@@ -0,0 +1,36 @@
+use rltk::DiceType;
+use super::{Attributes, Skills};
+
+/// What an attribute adds to anything built on it, 10 being average.
+pub fn attr_bonus(value: i32) -> i32 {
+    (value - 10) / 2
+}
+
+pub fn max_hp(attributes: &Attributes, level: i32) -> i32 {
+    attributes.fitness * 2 + (level - 1) * i32::max(1, 5 + attr_bonus(attributes.fitness))
+}
+
+/// Added to melee attack rolls.
+pub fn attack_bonus(attributes: &Attributes, skills: &Skills) -> i32 {
+    attr_bonus(attributes.might) + skills.melee
+}
+
+/// Natural weapon damage with might on top.
+pub fn melee_damage(attributes: &Attributes, natural: DiceType) -> DiceType {
+    DiceType { bonus: natural.bonus + attr_bonus(attributes.might), ..natural }
+}
+
+/// Added to the armor class melee attacks have to beat.
+pub fn dodge(attributes: &Attributes, skills: &Skills) -> i32 {
+    attr_bonus(attributes.quickness) + skills.dodge
+}
+
+/// Extra damage on anything cast from a scroll.
+pub fn spell_bonus(attributes: &Attributes, skills: &Skills) -> i32 {
+    attr_bonus(attributes.intelligence) + skills.magic
+}
+
+/// How many items fit in a backpack.
+pub fn carry_capacity(attributes: &Attributes) -> i32 {
+    i32::max(1, 10 + attr_bonus(attributes.might) * 2)
+}
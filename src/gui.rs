@@ -1,7 +1,8 @@
 use rltk::{RGB, Rltk, Point, VirtualKeyCode};
 use specs::prelude::*;
+use std::time::Duration;
 
-use super::{CombatStats, Player, gamelog::GameLog, Map, Position, Name, InBackpack, State, Viewshed};
+use super::{CombatStats, Player, gamelog::GameLog, Map, Position, Name, InBackpack, State, Viewshed, GameSeed, RunState, StatusEffect, Hidden, HungerClock, HungerState, Experience, LevelUpChoice, Attributes, Skills, Equipped, MeleePowerBonus, DefenseBonus, Brain, Ally, gamesystem, saveload_system, replay::ReplayRecorder};
 
 
 pub fn draw_ui(ecs: &World, ctx: &mut Rltk) {
@@ -10,13 +11,21 @@ pub fn draw_ui(ecs: &World, ctx: &mut Rltk) {
     let mouse_pos = ctx.mouse_pos();
     ctx.set_bg(mouse_pos.0, mouse_pos.1, RGB::named(rltk::MAGENTA));
 
+    let map = ecs.fetch::<Map>();
+    let depth = format!("Depth: {}", map.depth);
+    ctx.print_color(2, 43, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), &depth);
+
     let combat_stats = ecs.read_storage::<CombatStats>();
     let player = ecs.read_storage::<Player>();
-    for (_player, stats) in (&player, &combat_stats).join() {
+    let experience = ecs.read_storage::<Experience>();
+    for (_player, stats, exp) in (&player, &combat_stats, &experience).join() {
         let health = format!{"HP: {}/{} ", stats.hp, stats.max_hp};
 
         ctx.print_color(12, 43, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), &health);
-        ctx.draw_bar_horizontal(28, 43, 51, stats.hp, stats.max_hp, RGB::named(rltk::RED), RGB::named(rltk::BLACK) )
+        ctx.draw_bar_horizontal(26, 43, 24, stats.hp, stats.max_hp, RGB::named(rltk::RED), RGB::named(rltk::BLACK) );
+
+        let level = format!("Level: {}  XP: {}/{}", exp.level, exp.xp, exp.xp_to_next_level());
+        ctx.print_color(52, 43, RGB::named(rltk::GOLD), RGB::named(rltk::BLACK), &level);
     }
 
     let log = ecs.fetch::<GameLog>();
@@ -27,19 +36,65 @@ pub fn draw_ui(ecs: &World, ctx: &mut Rltk) {
         y += 1;
     }
 
+    let seed = ecs.fetch::<GameSeed>();
+    ctx.print_color(2, 49, RGB::named(rltk::GREY), RGB::named(rltk::BLACK), &format!("Seed: {}", seed.seed));
+
+    // hunger and the player's active effects, right aligned along the bottom edge
+    let player_entity = ecs.fetch::<Entity>();
+    let mut indicators: Vec<(String, RGB)> = Vec::new();
+    if let Some(clock) = ecs.read_storage::<HungerClock>().get(*player_entity) {
+        match clock.state {
+            HungerState::Normal => {}
+            HungerState::WellFed => indicators.push((clock.state.name().to_string(), RGB::named(rltk::GREEN))),
+            HungerState::Hungry => indicators.push((clock.state.name().to_string(), RGB::named(rltk::ORANGE))),
+            HungerState::Starving => indicators.push((clock.state.name().to_string(), RGB::named(rltk::RED))),
+        }
+    }
+    let status_effects = ecs.read_storage::<StatusEffect>();
+    for effect in status_effects.join().filter(|effect| effect.target == *player_entity) {
+        indicators.push((format!("{} ({})", effect.kind.name(), effect.turns), RGB::named(rltk::ORANGE)));
+    }
+
+    let mut x = 78;
+    for (text, colour) in indicators.iter() {
+        x -= text.len() as i32;
+        ctx.print_color(x, 49, *colour, RGB::named(rltk::BLACK), text);
+        x -= 1;
+    }
+
+    draw_party(ecs, ctx);
     draw_tooltip(ecs, ctx);
 }
 
+/// Allies and their health, in the top right corner of the map.
+fn draw_party(ecs: &World, ctx: &mut Rltk) {
+    let names = ecs.read_storage::<Name>();
+    let combat_stats = ecs.read_storage::<CombatStats>();
+    let allies = ecs.read_storage::<Ally>();
+
+    let party: Vec<(&Name, &CombatStats)> = (&names, &combat_stats, &allies).join().map(|(name, stats, _ally)| (name, stats)).collect();
+    if party.is_empty() { return; }
+
+    ctx.draw_box(56, 0, 23, party.len() as i32 + 1, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
+    ctx.print_color(58, 0, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Party");
+    for (i, (name, stats)) in party.iter().enumerate() {
+        let y = 1 + i as i32;
+        ctx.print(58, y, &name.name);
+        ctx.print_color(70, y, RGB::named(rltk::RED), RGB::named(rltk::BLACK), format!("{}/{}", stats.hp, stats.max_hp));
+    }
+}
+
 fn draw_tooltip(ecs: &World, ctx: &mut Rltk) {
     let map = ecs.fetch::<Map>();
     let names = ecs.read_storage::<Name>();
     let positions = ecs.read_storage::<Position>();
+    let hidden = ecs.read_storage::<Hidden>();
 
     let mouse_pos = ctx.mouse_pos();
     if mouse_pos.0 >= map.width || mouse_pos.1 >= map.height {return;}
     let mut tooltip: Vec<String> = Vec::new();
 
-    for (name, position) in (&names, &positions).join() {
+    for (name, position, _hidden) in (&names, &positions, !&hidden).join() {
         let idx = map.xy_idx(position.x, position.y);
         if position.x == mouse_pos.0 && position.y == mouse_pos.1 && map.visible_tiles[idx] {
             tooltip.push(name.name.to_string());
@@ -84,18 +139,55 @@ fn draw_tooltip(ecs: &World, ctx: &mut Rltk) {
     }
 }
 
+/// Debug view of every monster's AI mode, with a `*` on wherever it's headed.
+pub fn draw_ai_debug(ecs: &World, ctx: &mut Rltk) {
+    let positions = ecs.read_storage::<Position>();
+    let brains = ecs.read_storage::<Brain>();
+
+    for (pos, brain) in (&positions, &brains).join() {
+        if let Some((x, y)) = brain.mode.destination() {
+            ctx.set(x, y, RGB::named(rltk::MAGENTA), RGB::named(rltk::BLACK), rltk::to_cp437('*'));
+        }
+        ctx.print_color(pos.x + 1, pos.y, RGB::named(rltk::MAGENTA), RGB::named(rltk::BLACK), brain.mode.name());
+    }
+}
+
+/// Debug view of how long each system took on its last run, in microseconds.
+pub fn draw_system_timings(timings: &[(&str, Duration)], ctx: &mut Rltk) {
+    ctx.draw_box(0, 0, 27, (timings.len() + 1) as i32, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
+    ctx.print_color(2, 0, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "System timings");
+    for (y, (name, elapsed)) in timings.iter().enumerate() {
+        ctx.print(2, y as i32 + 1, name);
+        ctx.print_color(18, y as i32 + 1, RGB::named(rltk::CYAN), RGB::named(rltk::BLACK), &format!("{:>6}us", elapsed.as_micros()));
+    }
+}
+
 // TODO change enums to have "Used" and "Dropped" so that I can consolidate the inventory system
 #[derive(PartialEq, Copy, Clone)]
 pub enum ItemMenuResult {Cancel, NoResponse, Selected}
 
-pub fn show_inventory(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option<Entity>) {
-    let player_entity = gs.ecs.fetch::<Entity>();
-    let names = gs.ecs.read_storage::<Name>();    
-    let backpack = gs.ecs.read_storage::<InBackpack>();
-    let entities = gs.ecs.entities();
+/// Everything the player has on them, carried first and then worn, with worn items marked.
+/// Picking a worn item in the inventory takes it off, dropping one takes it off and drops it.
+fn player_items(ecs: &World) -> Vec<(Entity, String)> {
+    let player_entity = ecs.fetch::<Entity>();
+    let names = ecs.read_storage::<Name>();
+    let backpack = ecs.read_storage::<InBackpack>();
+    let equipped = ecs.read_storage::<Equipped>();
+    let entities = ecs.entities();
+
+    let mut items: Vec<(Entity, String)> = (&entities, &backpack, &names).join()
+        .filter(|(_entity, pack, _name)| pack.owner == *player_entity)
+        .map(|(entity, _pack, name)| (entity, name.name.clone()))
+        .collect();
+    items.extend((&entities, &equipped, &names).join()
+        .filter(|(_entity, worn, _name)| worn.owner == *player_entity)
+        .map(|(entity, _worn, name)| (entity, format!("{} (equipped)", name.name))));
+    items
+}
 
-    let inventory = (&backpack, &names).join().filter(|item| item.0.owner == *player_entity);
-    let count = inventory.count();
+pub fn show_inventory(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option<Entity>) {
+    let inventory = player_items(&gs.ecs);
+    let count = inventory.len();
 
     let mut y = (25 - (count / 2)) as i32;
     ctx.draw_box(15, y-2, 31, (count+3) as i32, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
@@ -104,13 +196,13 @@ pub fn show_inventory(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option
 
     let mut equippable: Vec<Entity> = Vec::new();
     let mut j = 0;
-    for (entity, _pack, name) in (&entities, &backpack, &names).join().filter(|item| item.1.owner == *player_entity) {
+    for (entity, name) in inventory.iter() {
         ctx.set(17, y, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), rltk::to_cp437('('));
         ctx.set(18, y, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), 97+j as rltk::FontCharType);
         ctx.set(19, y, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), rltk::to_cp437(')'));
 
-        ctx.print(21, y, &name.name.to_string());
-        equippable.push(entity);
+        ctx.print(21, y, name);
+        equippable.push(*entity);
         y += 1;
         j += 1;
     }
@@ -134,13 +226,8 @@ pub fn show_inventory(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option
 }
 
 pub fn drop_item_menu(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option<Entity>) {
-    let player_entity = gs.ecs.fetch::<Entity>();
-    let names = gs.ecs.read_storage::<Name>();    
-    let backpack = gs.ecs.read_storage::<InBackpack>();
-    let entities = gs.ecs.entities();
-
-    let inventory = (&backpack, &names).join().filter(|item| item.0.owner == *player_entity);
-    let count = inventory.count();
+    let inventory = player_items(&gs.ecs);
+    let count = inventory.len();
 
     let mut y = (25 - (count / 2)) as i32;
     ctx.draw_box(15, y-2, 31, (count+3) as i32, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
@@ -149,13 +236,13 @@ pub fn drop_item_menu(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option
 
     let mut equippable: Vec<Entity> = Vec::new();
     let mut j = 0;
-    for (entity, _pack, name) in (&entities, &backpack, &names).join().filter(|item| item.1.owner == *player_entity) {
+    for (entity, name) in inventory.iter() {
         ctx.set(17, y, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), rltk::to_cp437('('));
         ctx.set(18, y, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), 97+j as rltk::FontCharType);
         ctx.set(19, y, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), rltk::to_cp437(')'));
 
-        ctx.print(21, y, &name.name.to_string());
-        equippable.push(entity);
+        ctx.print(21, y, name);
+        equippable.push(*entity);
         y += 1;
         j += 1;
     }
@@ -214,4 +301,178 @@ pub fn ranged_target(gs: &mut State, ctx: &mut Rltk, range: i32) -> (ItemMenuRes
         }
     }
     (ItemMenuResult::NoResponse, None)
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum MainMenuSelection { NewGame, LoadGame, Quit }
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum MainMenuResult { NoSelection { selected: MainMenuSelection }, Selected { selected: MainMenuSelection } }
+
+pub fn main_menu(gs: &mut State, ctx: &mut Rltk) -> MainMenuResult {
+    let runstate = gs.ecs.fetch::<RunState>();
+
+    ctx.print_color_centered(15, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Roguelike");
+
+    let mut options = vec![(MainMenuSelection::NewGame, "New Game")];
+    if saveload_system::does_save_exist() {
+        options.push((MainMenuSelection::LoadGame, "Load Game"));
+    }
+    options.push((MainMenuSelection::Quit, "Quit"));
+
+    if let RunState::MainMenu{ menu_selection: selection } = *runstate {
+        for (i, (option, label)) in options.iter().enumerate() {
+            let fg = if selection == *option { RGB::named(rltk::MAGENTA) } else { RGB::named(rltk::WHITE) };
+            ctx.print_color_centered(24 + i as i32, fg, RGB::named(rltk::BLACK), label);
+        }
+
+        let current = options.iter().position(|o| o.0 == selection).unwrap_or(0);
+        match ctx.key {
+            None => return MainMenuResult::NoSelection{ selected: options[current].0 },
+            Some(key) => {
+                match key {
+                    VirtualKeyCode::Escape => return MainMenuResult::NoSelection{ selected: MainMenuSelection::Quit },
+                    VirtualKeyCode::Up |
+                    VirtualKeyCode::W |
+                    VirtualKeyCode::K => {
+                        let next = if current == 0 { options.len() - 1 } else { current - 1 };
+                        return MainMenuResult::NoSelection{ selected: options[next].0 }
+                    }
+                    VirtualKeyCode::Down |
+                    VirtualKeyCode::S |
+                    VirtualKeyCode::J => {
+                        let next = (current + 1) % options.len();
+                        return MainMenuResult::NoSelection{ selected: options[next].0 }
+                    }
+                    VirtualKeyCode::Return => return MainMenuResult::Selected{ selected: options[current].0 },
+                    _ => return MainMenuResult::NoSelection{ selected: options[current].0 }
+                }
+            }
+        }
+    }
+
+    MainMenuResult::NoSelection { selected: MainMenuSelection::NewGame }
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum LevelUpResult { NoSelection, Selected { choice: LevelUpChoice } }
+
+pub fn level_up(gs: &mut State, ctx: &mut Rltk) -> LevelUpResult {
+    let options = [
+        (LevelUpChoice::Might, "Might: hit harder, carry more"),
+        (LevelUpChoice::Fitness, "Fitness: more hit points"),
+        (LevelUpChoice::Quickness, "Quickness: harder to hit"),
+        (LevelUpChoice::Intelligence, "Intelligence: stronger scrolls"),
+    ];
+
+    let player_entity = gs.ecs.fetch::<Entity>();
+    let level = gs.ecs.read_storage::<Experience>().get(*player_entity).map(|exp| exp.level + 1).unwrap_or(1);
+
+    let y = 22;
+    ctx.draw_box(20, y-2, 42, (options.len()+3) as i32, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
+    ctx.print_color(23, y-2, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), format!("Level {}! Choose an attribute", level));
+
+    for (j, (_choice, label)) in options.iter().enumerate() {
+        let row = y + j as i32;
+        ctx.set(22, row, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), rltk::to_cp437('('));
+        ctx.set(23, row, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), 97+j as rltk::FontCharType);
+        ctx.set(24, row, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), rltk::to_cp437(')'));
+        ctx.print(26, row, label);
+    }
+
+    match ctx.key {
+        None => LevelUpResult::NoSelection,
+        Some(key) => {
+            let selection = rltk::letter_to_option(key);
+            if selection > -1 && selection < options.len() as i32 {
+                return LevelUpResult::Selected{ choice: options[selection as usize].0 };
+            }
+            LevelUpResult::NoSelection
+        }
+    }
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum CharacterSheetResult { NoResponse, Close }
+
+pub fn character_sheet(ecs: &World, ctx: &mut Rltk) -> CharacterSheetResult {
+    let player_entity = ecs.fetch::<Entity>();
+    let attributes = ecs.read_storage::<Attributes>();
+    let skills = ecs.read_storage::<Skills>();
+    let combat_stats = ecs.read_storage::<CombatStats>();
+    let experience = ecs.read_storage::<Experience>();
+    let equipped = ecs.read_storage::<Equipped>();
+    let power_bonuses = ecs.read_storage::<MeleePowerBonus>();
+    let defense_bonuses = ecs.read_storage::<DefenseBonus>();
+    let backpack = ecs.read_storage::<InBackpack>();
+
+    let (attrs, skill, stats, exp) = match (attributes.get(*player_entity), skills.get(*player_entity), combat_stats.get(*player_entity), experience.get(*player_entity)) {
+        (Some(a), Some(s), Some(c), Some(e)) => (a, s, c, e),
+        _ => return CharacterSheetResult::Close
+    };
+
+    let power_bonus: i32 = (&power_bonuses, &equipped).join().filter(|(_, eq)| eq.owner == *player_entity).map(|(b, _)| b.power).sum();
+    let defense_bonus: i32 = (&defense_bonuses, &equipped).join().filter(|(_, eq)| eq.owner == *player_entity).map(|(b, _)| b.defense).sum();
+    let carried = backpack.join().filter(|pack| pack.owner == *player_entity).count();
+    let damage = gamesystem::melee_damage(attrs, stats.damage);
+
+    let white = RGB::named(rltk::WHITE);
+    let black = RGB::named(rltk::BLACK);
+    let bonus = |value: i32| format!("{:+}", gamesystem::attr_bonus(value));
+
+    ctx.draw_box(15, 8, 50, 26, white, black);
+    ctx.print_color(18, 8, RGB::named(rltk::YELLOW), black, "Character Sheet");
+    ctx.print_color(18, 10, RGB::named(rltk::GOLD), black, format!("Level {}, {}/{} xp", exp.level, exp.xp, exp.xp_to_next_level()));
+
+    ctx.print_color(18, 12, RGB::named(rltk::CYAN), black, "Attributes");
+    ctx.print(18, 13, format!("Might         {:>3} ({})", attrs.might, bonus(attrs.might)));
+    ctx.print(18, 14, format!("Fitness       {:>3} ({})", attrs.fitness, bonus(attrs.fitness)));
+    ctx.print(18, 15, format!("Quickness     {:>3} ({})", attrs.quickness, bonus(attrs.quickness)));
+    ctx.print(18, 16, format!("Intelligence  {:>3} ({})", attrs.intelligence, bonus(attrs.intelligence)));
+
+    ctx.print_color(18, 18, RGB::named(rltk::CYAN), black, "Skills");
+    ctx.print(18, 19, format!("Melee         {:>3}", skill.melee));
+    ctx.print(18, 20, format!("Dodge         {:>3}", skill.dodge));
+    ctx.print(18, 21, format!("Magic         {:>3}", skill.magic));
+
+    ctx.print_color(18, 23, RGB::named(rltk::CYAN), black, "Derived");
+    ctx.print(18, 24, format!("Hit points    {}/{}", stats.hp, stats.max_hp));
+    ctx.print(18, 25, format!("Attack        {:+}", gamesystem::attack_bonus(attrs, skill) + power_bonus));
+    ctx.print(18, 26, format!("Damage        {}d{}{:+}", damage.n_dice, damage.die_type, damage.bonus + power_bonus));
+    ctx.print(18, 27, format!("Dodge         {:+}", gamesystem::dodge(attrs, skill) + defense_bonus));
+    ctx.print(18, 28, format!("Spell bonus   {:+}", gamesystem::spell_bonus(attrs, skill)));
+    ctx.print(18, 29, format!("Carrying      {}/{}", carried, gamesystem::carry_capacity(attrs)));
+
+    ctx.print_color(18, 34, RGB::named(rltk::YELLOW), black, "ESC to close");
+
+    match ctx.key {
+        Some(VirtualKeyCode::Escape) | Some(VirtualKeyCode::P) => CharacterSheetResult::Close,
+        _ => CharacterSheetResult::NoResponse
+    }
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum GameOverResult { NoSelection, QuitToMenu }
+
+pub fn game_over(ecs: &World, ctx: &mut Rltk) -> GameOverResult {
+    end_screen(ecs, ctx, "Your journey has ended!", RGB::named(rltk::RED), "One day, we'll tell you all about how you did.")
+}
+
+pub fn victory(ecs: &World, ctx: &mut Rltk) -> GameOverResult {
+    end_screen(ecs, ctx, "You have cleared the dungeon!", RGB::named(rltk::GREEN), "Nothing down here is left alive but you.")
+}
+
+fn end_screen(ecs: &World, ctx: &mut Rltk, title: &str, title_colour: RGB, flavour: &str) -> GameOverResult {
+    let turns = ecs.fetch::<ReplayRecorder>().actions.len();
+
+    ctx.print_color_centered(15, title_colour, RGB::named(rltk::BLACK), title);
+    ctx.print_color_centered(17, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), flavour);
+    ctx.print_color_centered(19, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), format!("You lasted {} turns.", turns));
+
+    ctx.print_color_centered(22, RGB::named(rltk::MAGENTA), RGB::named(rltk::BLACK), "Press any key to return to the menu.");
+
+    match ctx.key {
+        None => GameOverResult::NoSelection,
+        Some(_) => GameOverResult::QuitToMenu
+    }
 }
\ No newline at end of file
@@ -0,0 +1,139 @@
+use specs::prelude::*;
+use super::{HungerClock, HungerState, RunState, SufferDamage, TurnClock, Name, events::{EventQueue, GameEvent, DamageKind}};
+
+/// Turns spent in each hunger state before sliding into the next one.
+pub const WELL_FED_DURATION: i32 = 20;
+pub const HUNGER_STAGE_DURATION: i32 = 200;
+/// Damage taken every turn once starving.
+const STARVATION_DAMAGE: i32 = 1;
+
+pub struct HungerSystem {}
+
+impl<'a> System<'a> for HungerSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, HungerClock>,
+        ReadExpect<'a, RunState>,
+        ReadExpect<'a, TurnClock>,
+        WriteStorage<'a, SufferDamage>,
+        WriteExpect<'a, EventQueue>,
+        ReadStorage<'a, Name>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (entities, mut hunger_clock, runstate, turn_clock, mut inflict_damage, mut events, names) = data;
+
+        if *runstate != RunState::Ticking || !turn_clock.new_turn {return;}
+
+        for (entity, clock) in (&entities, &mut hunger_clock).join() {
+            clock.duration -= 1;
+            if clock.duration > 0 { continue; }
+
+            let changed = match clock.state {
+                HungerState::WellFed => {
+                    clock.state = HungerState::Normal;
+                    clock.duration = HUNGER_STAGE_DURATION;
+                    true
+                }
+                HungerState::Normal => {
+                    clock.state = HungerState::Hungry;
+                    clock.duration = HUNGER_STAGE_DURATION;
+                    true
+                }
+                HungerState::Hungry => {
+                    clock.state = HungerState::Starving;
+                    clock.duration = HUNGER_STAGE_DURATION;
+                    true
+                }
+                HungerState::Starving => {
+                    // stays starving until something gets eaten
+                    clock.duration = 0;
+                    SufferDamage::new_damage(&mut inflict_damage, entity, STARVATION_DAMAGE, None);
+                    let target_name = names.get(entity).map(|n| n.name.clone()).unwrap_or_default();
+                    events.emit(GameEvent::DamageDealt { source: None, source_name: "Hunger".to_string(), target: entity, target_name, amount: STARVATION_DAMAGE, kind: DamageKind::Starvation });
+                    false
+                }
+            };
+
+            if changed {
+                events.emit(GameEvent::HungerChanged { entity, state: clock.state });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{State, InBackpack, Position, WantsToUse, inventory_system::ItemUseSystem, raws};
+
+    fn set_clock(gs: &mut State, entity: Entity, state: HungerState, duration: i32) {
+        let mut clocks = gs.ecs.write_storage::<HungerClock>();
+        let clock = clocks.get_mut(entity).unwrap();
+        clock.state = state;
+        clock.duration = duration;
+    }
+
+    fn tick(gs: &mut State, new_turn: bool) {
+        gs.ecs.write_resource::<TurnClock>().new_turn = new_turn;
+        HungerSystem{}.run_now(&gs.ecs);
+    }
+
+    fn state_of(gs: &State, entity: Entity) -> HungerState {
+        gs.ecs.read_storage::<HungerClock>().get(entity).unwrap().state
+    }
+
+    #[test]
+    fn the_clock_only_runs_on_new_turns() {
+        let mut gs = State::new_game(1, None, false, raws::load_raws().unwrap());
+        let player = *gs.ecs.fetch::<Entity>();
+        *gs.ecs.write_resource::<RunState>() = RunState::Ticking;
+        gs.ecs.write_resource::<EventQueue>().clear();
+        set_clock(&mut gs, player, HungerState::WellFed, 2);
+
+        tick(&mut gs, false);
+        tick(&mut gs, true);
+        assert_eq!(state_of(&gs, player), HungerState::WellFed);
+        tick(&mut gs, true);
+        assert_eq!(state_of(&gs, player), HungerState::Normal);
+        assert_eq!(gs.ecs.read_storage::<HungerClock>().get(player).unwrap().duration, HUNGER_STAGE_DURATION);
+        assert!(gs.ecs.fetch::<EventQueue>().iter().any(|event| matches!(event,
+            GameEvent::HungerChanged { entity, state: HungerState::Normal } if *entity == player)));
+    }
+
+    #[test]
+    fn starving_hurts_every_turn() {
+        let mut gs = State::new_game(1, None, false, raws::load_raws().unwrap());
+        let player = *gs.ecs.fetch::<Entity>();
+        *gs.ecs.write_resource::<RunState>() = RunState::Ticking;
+        set_clock(&mut gs, player, HungerState::Hungry, 1);
+
+        tick(&mut gs, true);
+        assert_eq!(state_of(&gs, player), HungerState::Starving);
+        for _ in 0..HUNGER_STAGE_DURATION - 1 { tick(&mut gs, true); }
+        assert!(gs.ecs.read_storage::<SufferDamage>().get(player).is_none(), "a full stage of starving before it hurts");
+        for _ in 0..3 { tick(&mut gs, true); }
+        assert_eq!(state_of(&gs, player), HungerState::Starving);
+        let damage = gs.ecs.read_storage::<SufferDamage>().get(player).unwrap().amount.clone();
+        assert_eq!(damage, vec![(STARVATION_DAMAGE, None); 3]);
+    }
+
+    #[test]
+    fn eating_fills_you_up() {
+        let mut gs = State::new_game(1, None, false, raws::load_raws().unwrap());
+        let player = *gs.ecs.fetch::<Entity>();
+        set_clock(&mut gs, player, HungerState::Starving, 0);
+        let rations = raws::spawn_named_entity(&mut gs.ecs, "Rations", 0, 0).unwrap();
+        gs.ecs.write_storage::<Position>().remove(rations);
+        gs.ecs.write_storage::<InBackpack>().insert(rations, InBackpack { owner: player }).unwrap();
+        gs.ecs.write_storage::<WantsToUse>().insert(player, WantsToUse { item: rations, target: None }).unwrap();
+
+        ItemUseSystem{}.run_now(&gs.ecs);
+        gs.ecs.maintain();
+
+        assert_eq!(state_of(&gs, player), HungerState::WellFed);
+        assert_eq!(gs.ecs.read_storage::<HungerClock>().get(player).unwrap().duration, WELL_FED_DURATION);
+        assert!(!gs.ecs.is_alive(rations));
+    }
+}
@@ -1,5 +1,5 @@
 use specs::prelude::*;
-use super::{WantsToPickupItem, Name, InBackpack, Position, gamelog::GameLog, CombatStats, WantsToUse, Consumable, ProvidesHealing, WantsToDrop, InflictDamage, SufferDamage, Map, AreaOfEffect};
+use super::{WantsToPickupItem, Name, InBackpack, Position, CombatStats, WantsToUse, Consumable, ProvidesHealing, WantsToDrop, InflictDamage, SufferDamage, Map, AreaOfEffect, Equippable, Equipped, InflictsStatus, StatusEffect, status_effect_system::apply_status_effect, Attributes, Skills, gamesystem, ProvidesFood, HungerClock, HungerState, hunger_system::WELL_FED_DURATION, events::{EventQueue, GameEvent, DamageKind, UseOutcome}, Hidden};
 
 //TODO refactor inventory system to select items with movement keys then have hotkeys for use/drop/etc
 
@@ -7,23 +7,21 @@ pub struct InventoryCollectionSystem {}
 
 impl<'a> System<'a> for InventoryCollectionSystem {
     #[allow(clippy::type_complexity)]
-    type SystemData = (ReadExpect<'a, Entity>,
-                        WriteExpect<'a, GameLog>,
+    type SystemData = (WriteExpect<'a, EventQueue>,
                         WriteStorage<'a, WantsToPickupItem>,
                         WriteStorage<'a, Position>,
                         ReadStorage<'a, Name>,
                         WriteStorage<'a, InBackpack>);
 
     fn run(&mut self, data: Self::SystemData) {
-        let (player_entity, mut gamelog, mut wants_to_pickup, mut positions, names, mut backpack) = data;
+        let (mut events, mut wants_to_pickup, mut positions, names, mut backpack) = data;
 
         for pickup in wants_to_pickup.join() {
             positions.remove(pickup.item);
             backpack.insert(pickup.item, InBackpack{owner: pickup.collected_by}).expect("Unable to insert backpack entry");
 
-            if pickup.collected_by == *player_entity {
-                gamelog.entries.push(format!("You pick up the {}", names.get(pickup.item).unwrap().name));
-            }
+            let name = names.get(pickup.item).unwrap().name.clone();
+            events.emit(GameEvent::ItemPickedUp { by: pickup.collected_by, item: pickup.item, name });
         }
         wants_to_pickup.clear();
     }
@@ -33,8 +31,9 @@ impl<'a> System<'a> for InventoryCollectionSystem {
 pub struct ItemUseSystem {}
 
 impl<'a> System<'a> for ItemUseSystem {
+    #[allow(clippy::type_complexity)]
     type SystemData = (ReadExpect<'a, Entity>,
-                        WriteExpect<'a, GameLog>,
+                        WriteExpect<'a, EventQueue>,
                         ReadExpect<'a, Map>,
                         Entities<'a>,
                         WriteStorage<'a, WantsToUse>,
@@ -44,12 +43,35 @@ impl<'a> System<'a> for ItemUseSystem {
                         ReadStorage<'a, Consumable>,
                         ReadStorage<'a, InflictDamage>,
                         WriteStorage<'a, SufferDamage>,
-                        ReadStorage<'a, AreaOfEffect>);
+                        ReadStorage<'a, AreaOfEffect>,
+                        ReadStorage<'a, Equippable>,
+                        WriteStorage<'a, Equipped>,
+                        WriteStorage<'a, InBackpack>,
+                        ReadStorage<'a, InflictsStatus>,
+                        WriteStorage<'a, StatusEffect>,
+                        ReadStorage<'a, Attributes>,
+                        ReadStorage<'a, Skills>,
+                        ReadStorage<'a, ProvidesFood>,
+                        WriteStorage<'a, HungerClock>,
+                        ReadStorage<'a, Hidden>);
 
     fn run(&mut self, data: Self::SystemData){
-        let (player_entity, mut gamelog, map, entities, mut wants_to_use, names, healing, mut combat_stats, consumables, inflict_damage, mut suffer_damage, aoe) = data;
+        let (player_entity, mut events, map, entities, mut wants_to_use, names, healing, mut combat_stats, consumables, inflict_damage, mut suffer_damage, aoe, equippable, mut equipped, mut backpack, inflicts_status, mut status_effects, attributes, skills, provides_food, mut hunger_clocks, hidden) = data;
 
         for (entity, useitem) in (&entities, &wants_to_use).join() {            
+            let user_name = names.get(entity).map(|n| n.name.clone()).unwrap_or_default();
+            let item_name = names.get(useitem.item).unwrap().name.clone();
+            let mut outcomes: Vec<UseOutcome> = Vec::new();
+
+            // using something that's already worn takes it off
+            if equipped.get(useitem.item).map(|worn| worn.owner == entity).unwrap_or(false) {
+                equipped.remove(useitem.item);
+                backpack.insert(useitem.item, InBackpack{ owner: entity }).expect("Unable to insert backpack entry");
+                outcomes.push(UseOutcome::Unequipped { item: item_name.clone() });
+                events.emit(GameEvent::ItemUsed { user: entity, user_name, item: useitem.item, name: item_name, outcomes });
+                continue;
+            }
+
             let mut used_item = true;
         
             //targetting
@@ -80,6 +102,37 @@ impl<'a> System<'a> for ItemUseSystem {
                 }
             }
 
+            // equipping swaps out whatever the user already has in that slot
+            if let Some(can_equip) = equippable.get(useitem.item) {
+                let target_slot = can_equip.slot;
+
+                let mut to_unequip: Vec<Entity> = Vec::new();
+                for (item_entity, already_equipped, name) in (&entities, &equipped, &names).join() {
+                    if already_equipped.owner == entity && already_equipped.slot == target_slot {
+                        to_unequip.push(item_entity);
+                        outcomes.push(UseOutcome::Unequipped { item: name.name.clone() });
+                    }
+                }
+                for item in to_unequip.iter() {
+                    equipped.remove(*item);
+                    backpack.insert(*item, InBackpack{ owner: entity }).expect("Unable to insert backpack entry");
+                }
+
+                equipped.insert(useitem.item, Equipped{ owner: entity, slot: target_slot }).expect("Unable to equip item");
+                backpack.remove(useitem.item);
+                outcomes.push(UseOutcome::Equipped);
+            }
+
+            if provides_food.get(useitem.item).is_some() {
+                for target in targets.iter() {
+                    if let Some(clock) = hunger_clocks.get_mut(*target) {
+                        clock.state = HungerState::WellFed;
+                        clock.duration = WELL_FED_DURATION;
+                        outcomes.push(UseOutcome::Eaten { target: *target });
+                    }
+                }
+            }
+
             let item_heals = healing.get(useitem.item);
             match item_heals {
                 None => {}
@@ -88,32 +141,58 @@ impl<'a> System<'a> for ItemUseSystem {
                         let stats = combat_stats.get_mut(*target);
                         if let Some(stats) = stats {
                             stats.hp = i32::min(stats.max_hp, stats.hp + healer.heal_amount);
-                            if entity == *player_entity {
-                                gamelog.entries.push(format!("You use the {}, healing {} hp", names.get(useitem.item).unwrap().name, healer.heal_amount));
-                            }
+                            outcomes.push(UseOutcome::Healed { target: *target, amount: healer.heal_amount });
                         }
                     }
                 }
             }
 
+            if let Some(status) = inflicts_status.get(useitem.item) {
+                for mob in targets.iter() {
+                    if combat_stats.get(*mob).is_none() || hidden.get(*mob).is_some() { continue; }
+                    apply_status_effect(&entities, &mut status_effects, *mob, entity, status.kind, status.turns);
+                    let target_name = names.get(*mob).unwrap().name.clone();
+                    outcomes.push(UseOutcome::StatusApplied { target: *mob, target_name, kind: status.kind });
+                }
+            }
+
             // If it inflicts damage, apply it to the target cell
             let item_damages = inflict_damage.get(useitem.item);
             match item_damages {
                 None => {}
                 Some(damage) => {
                     used_item = false;
+                    let spell_bonus = match (attributes.get(entity), skills.get(entity)) {
+                        (Some(attrs), Some(skill)) => gamesystem::spell_bonus(attrs, skill),
+                        _ => 0
+                    };
+                    let amount = i32::max(1, damage.damage + spell_bonus);
                     for mob in targets.iter() {
-                        SufferDamage::new_damage(&mut suffer_damage, *mob, damage.damage);
-                        if entity == *player_entity {
-                            let mob_name = names.get(*mob).unwrap();
-                            let item_name = names.get(useitem.item).unwrap();
-                            gamelog.entries.push(format!("You use {} on {}, inflicting {} hp.", item_name.name, mob_name.name, damage.damage));
-                        }
-                    
+                        // only things that can be hurt, and nothing the player hasn't found yet
+                        if combat_stats.get(*mob).is_none() || hidden.get(*mob).is_some() { continue; }
+                        SufferDamage::new_damage(&mut suffer_damage, *mob, amount, Some(entity));
+                        let target_name = names.get(*mob).unwrap().name.clone();
+                        events.emit(GameEvent::DamageDealt {
+                            source: Some(entity),
+                            source_name: user_name.clone(),
+                            target: *mob,
+                            target_name,
+                            amount,
+                            kind: DamageKind::Item { item: item_name.clone() }
+                        });
                         used_item = true;
                     }
                 }
             }
+
+            if used_item {
+                events.emit(GameEvent::ItemUsed { user: entity, user_name, item: useitem.item, name: item_name, outcomes });
+            }
+
+            // a scroll that hit nothing isn't spent
+            if used_item && consumables.get(useitem.item).is_some() {
+                entities.delete(useitem.item).expect("Delete failed");
+            }
         }
         wants_to_use.clear();
     }
@@ -123,17 +202,17 @@ pub struct ItemDropSystem {}
 
 impl<'a> System<'a> for ItemDropSystem {
     #[allow(clippy::type_complexity)]
-    type SystemData = ( ReadExpect<'a, Entity>,
-                        WriteExpect<'a, GameLog>,
+    type SystemData = ( WriteExpect<'a, EventQueue>,
                         Entities<'a>,
                         WriteStorage<'a, WantsToDrop>,
                         ReadStorage<'a, Name>,
                         WriteStorage<'a, Position>,
-                        WriteStorage<'a, InBackpack>
+                        WriteStorage<'a, InBackpack>,
+                        WriteStorage<'a, Equipped>
                       );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (player_entity, mut gamelog, entities, mut wants_to_drop, names, mut positions, mut backpack) = data;
+        let (mut events, entities, mut wants_to_drop, names, mut positions, mut backpack, mut equipped) = data;
 
         for (entity, to_drop) in (&entities, &wants_to_drop).join() {
             let mut dropper_pos: Position = Position { x: 0, y: 0 };
@@ -144,11 +223,38 @@ impl<'a> System<'a> for ItemDropSystem {
             }
             positions.insert(to_drop.item, Position { x: dropper_pos.x, y: dropper_pos.y }).expect("unable to insert position");
             backpack.remove(to_drop.item);
+            equipped.remove(to_drop.item);
 
-            if entity == *player_entity {
-                gamelog.entries.push(format!("You drop the {}", names.get(to_drop.item).unwrap().name));
-            }
+            let name = names.get(to_drop.item).unwrap().name.clone();
+            events.emit(GameEvent::ItemDropped { by: entity, item: to_drop.item, name });
         }
         wants_to_drop.clear();
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{State, MapIndexingSystem, raws};
+
+    #[test]
+    fn item_damage_passes_over_hidden_traps() {
+        let mut gs = State::new_game(1, None, false, raws::load_raws().unwrap());
+        let player = *gs.ecs.fetch::<Entity>();
+        let player_pos = *gs.ecs.fetch::<rltk::Point>();
+        let trap = raws::spawn_named_entity(&mut gs.ecs, "Bear Trap", player_pos.x + 1, player_pos.y).unwrap();
+        let scroll = raws::spawn_named_entity(&mut gs.ecs, "Scroll of Magic Missile", 0, 0).unwrap();
+        gs.ecs.write_storage::<Position>().remove(scroll);
+        gs.ecs.write_storage::<InBackpack>().insert(scroll, InBackpack { owner: player }).unwrap();
+        let target = Some(rltk::Point::new(player_pos.x + 1, player_pos.y));
+        gs.ecs.write_storage::<WantsToUse>().insert(player, WantsToUse { item: scroll, target }).unwrap();
+        gs.ecs.write_resource::<EventQueue>().clear();
+
+        MapIndexingSystem{}.run_now(&gs.ecs);
+        ItemUseSystem{}.run_now(&gs.ecs);
+        gs.ecs.maintain();
+
+        assert!(gs.ecs.is_alive(scroll), "a scroll that hit nothing isn't spent");
+        assert!(gs.ecs.read_storage::<SufferDamage>().get(trap).is_none());
+        assert_eq!(gs.ecs.fetch::<EventQueue>().iter().count(), 0);
+    }
+}
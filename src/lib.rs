@@ -0,0 +1,635 @@
+use rltk::{GameState, Rltk, Point};
+use specs::prelude::*;
+use specs::saveload::{SimpleMarker, SimpleMarkerAllocator};
+
+pub mod components;
+pub use components::*;
+pub mod map;
+pub use map::*;
+pub mod player;
+use player::*;
+pub mod rect;
+pub use rect::Rect;
+pub mod visibility_system;
+pub use visibility_system::VisibilitySystem;
+pub mod energy_system;
+pub use energy_system::*;
+pub mod monster_ai_system;
+pub use monster_ai_system::*;
+pub mod status_effect_system;
+pub use status_effect_system::*;
+pub mod hunger_system;
+pub use hunger_system::*;
+pub mod map_indexing_system;
+pub use map_indexing_system::*;
+pub mod trigger_system;
+pub use trigger_system::*;
+pub mod damage_system;
+pub use damage_system::*;
+pub mod melee_combat_system;
+pub use melee_combat_system::*;
+pub mod gui;
+pub mod gamelog;
+pub mod spawner;
+pub mod inventory_system;
+pub mod simulation;
+pub use simulation::*;
+pub mod saveload_system;
+pub mod replay;
+pub mod map_builders;
+pub mod raws;
+pub mod spawn_table;
+pub mod gamesystem;
+pub mod dispatcher;
+pub mod events;
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum RunState { 
+    AwaitingInput, 
+    PreRun, 
+    PlayerTurn, 
+    /// Handing out energy and letting monsters act until the player can afford to.
+    Ticking, 
+    ShowInventory, 
+    ShowDropItem, 
+    ShowTargetting { range: i32, item: Entity},
+    MainMenu { menu_selection: gui::MainMenuSelection },
+    SaveGame,
+    GameOver,
+    Victory,
+    NextLevel,
+    MapGeneration,
+    LevelUp,
+    ShowCharacterSheet
+}
+
+pub struct State {
+    pub ecs: World,
+    /// Plays back how each level was generated before it's entered.
+    pub show_mapgen: bool,
+    /// Draws what each monster's AI is up to over the map.
+    pub show_ai_debug: bool,
+    /// Lists how long each system took on its last run.
+    pub show_system_timings: bool,
+    /// Lets systems that don't share data run on separate threads.
+    pub parallel_systems: bool,
+    systems: Dispatcher<'static, 'static>,
+    subscribers: Dispatcher<'static, 'static>,
+    system_timings: dispatcher::SystemTimings,
+    mapgen_history: Vec<Map>,
+    mapgen_index: usize,
+    mapgen_timer: f32,
+}
+
+/// The seed every random roll in a run is drawn from, kept around so a run can be replayed.
+pub struct GameSeed {
+    pub seed: u64,
+}
+
+/// Puts `entities` in map order, top row first. Loading a game hands out entity ids afresh, so
+/// anything rolling dice for several entities in a round goes through them in this order
+/// instead, or a reloaded game would give the same rolls to different entities.
+pub fn sort_by_position<P>(entities: &mut [Entity], positions: &P) where P: specs::storage::GenericReadStorage<Component = Position> {
+    entities.sort_by_key(|entity| positions.get(*entity).map(|pos| (pos.y, pos.x)));
+}
+
+impl State {
+    /// Builds a fresh world with the map, the player and the first batch of monsters and items.
+    /// The same seed always builds the same world. `builder` forces one map generator for
+    /// every level, otherwise each depth picks its own. `show_mapgen` records how each level
+    /// is built so it can be played back. `raws` are the entity definitions loaded at startup.
+    pub fn new_game(seed: u64, builder: Option<map_builders::BuilderKind>, show_mapgen: bool, raws: raws::RawMaster) -> State {
+        let system_timings = dispatcher::SystemTimings::default();
+        let systems = dispatcher::build_dispatcher(&system_timings);
+        let subscribers = dispatcher::build_subscribers(&system_timings);
+        let mut gs = State {
+            ecs: World::new(),
+            show_mapgen,
+            show_ai_debug: false,
+            show_system_timings: false,
+            parallel_systems: false,
+            systems,
+            subscribers,
+            system_timings,
+            mapgen_history: Vec::new(),
+            mapgen_index: 0,
+            mapgen_timer: 0.0,
+        };
+
+        //register components
+        gs.ecs.register::<Position>();
+        gs.ecs.register::<Renderable>();
+        gs.ecs.register::<Player>();
+        gs.ecs.register::<Viewshed>();
+        gs.ecs.register::<Monster>();
+        gs.ecs.register::<Brain>();
+        gs.ecs.register::<Faction>();
+        gs.ecs.register::<Ally>();
+        gs.ecs.register::<Energy>();
+        gs.ecs.register::<MyTurn>();
+        gs.ecs.register::<AttackCost>();
+        gs.ecs.register::<Name>();
+        gs.ecs.register::<BlocksTile>();
+        gs.ecs.register::<CombatStats>();
+        gs.ecs.register::<Attributes>();
+        gs.ecs.register::<Skills>();
+        gs.ecs.register::<SufferDamage>();
+        gs.ecs.register::<Experience>();
+        gs.ecs.register::<HungerClock>();
+        gs.ecs.register::<ProvidesFood>();
+        gs.ecs.register::<GivesExperience>();
+        gs.ecs.register::<WantsToMelee>();
+        gs.ecs.register::<Item>();
+        gs.ecs.register::<ProvidesHealing>();
+        gs.ecs.register::<WantsToPickupItem>();
+        gs.ecs.register::<InBackpack>();
+        gs.ecs.register::<WantsToUse>();
+        gs.ecs.register::<WantsToDrop>();
+        gs.ecs.register::<Consumable>();
+        gs.ecs.register::<InflictDamage>();
+        gs.ecs.register::<Ranged>();
+        gs.ecs.register::<Spell>();
+        gs.ecs.register::<AreaOfEffect>();
+        gs.ecs.register::<StatusEffect>();
+        gs.ecs.register::<InflictsStatus>();
+        gs.ecs.register::<Hidden>();
+        gs.ecs.register::<EntryTrigger>();
+        gs.ecs.register::<EntityMoved>();
+        gs.ecs.register::<SingleActivation>();
+        gs.ecs.register::<TeleportsVictim>();
+        gs.ecs.register::<Alarm>();
+        gs.ecs.register::<Alerted>();
+        gs.ecs.register::<BlocksVisibility>();
+        gs.ecs.register::<Door>();
+        gs.ecs.register::<Equippable>();
+        gs.ecs.register::<Equipped>();
+        gs.ecs.register::<MeleePowerBonus>();
+        gs.ecs.register::<DefenseBonus>();
+        gs.ecs.register::<SimpleMarker<SerializeMe>>();
+        gs.ecs.register::<SerializationHelper>();
+
+        gs.ecs.insert(SimpleMarkerAllocator::<SerializeMe>::new());
+
+        // insert resources to ecs
+        gs.ecs.insert(rltk::RandomNumberGenerator::seeded(seed));
+        gs.ecs.insert(GameSeed { seed });
+        gs.ecs.insert(map_builders::BuilderChoice { kind: builder });
+        gs.ecs.insert(raws);
+        gs.ecs.insert(Map::new(1));
+        gs.ecs.insert(Point::new(0, 0));
+        let player_entity = spawner::player(&mut gs.ecs, 0, 0);
+        gs.ecs.insert(player_entity);
+        gs.ecs.insert(RunState::PreRun);
+        gs.ecs.insert(gamelog::GameLog {entries: vec!["Welcome to hell".to_string()]});
+        gs.ecs.insert(replay::ReplayRecorder::default());
+        gs.ecs.insert(TurnClock::default());
+        gs.ecs.insert(events::EventQueue::default());
+
+        gs.generate_world_map(1);
+
+        gs
+    }
+
+    /// Builds the map for `new_depth`, fills it and moves the player to its start.
+    fn generate_world_map(&mut self, new_depth: i32) {
+        let choice = self.ecs.fetch::<map_builders::BuilderChoice>().kind;
+        let mut builder = map_builders::builder_for(new_depth, choice, self.show_mapgen);
+        {
+            // the map comes off the game's rng so the seed still decides every level
+            let mut rng = self.ecs.write_resource::<rltk::RandomNumberGenerator>();
+            builder.build_map(&mut rng);
+        }
+        {
+            let mut worldmap_resource = self.ecs.write_resource::<Map>();
+            *worldmap_resource = builder.get_map();
+        }
+        builder.spawn_entities(&mut self.ecs);
+
+        self.mapgen_history = builder.get_snapshot_history();
+        self.mapgen_index = 0;
+        self.mapgen_timer = 0.0;
+
+        let player_start = builder.get_starting_position();
+        let mut player_position = self.ecs.write_resource::<Point>();
+        *player_position = Point::new(player_start.x, player_start.y);
+        let mut position_components = self.ecs.write_storage::<Position>();
+        let player_entity = self.ecs.fetch::<Entity>();
+        if let Some(player_pos_comp) = position_components.get_mut(*player_entity) {
+            player_pos_comp.x = player_start.x;
+            player_pos_comp.y = player_start.y;
+        }
+
+        let mut viewshed_components = self.ecs.write_storage::<Viewshed>();
+        if let Some(vs) = viewshed_components.get_mut(*player_entity) {
+            vs.dirty = true;
+        }
+    }
+
+    fn run_systems(&mut self) {
+        if self.parallel_systems {
+            self.systems.dispatch_par(&self.ecs);
+        } else {
+            self.systems.dispatch_seq(&self.ecs);
+        }
+        DamageSystem::delete_the_dead(&mut self.ecs);
+        self.subscribers.dispatch_seq(&self.ecs);
+        self.ecs.write_resource::<events::EventQueue>().clear();
+        self.ecs.maintain();
+    }
+
+    /// Runs the states that don't wait on the player and returns the state that follows.
+    /// Anything that needs input or a menu is handed back unchanged.
+    pub fn advance(&mut self, runstate: RunState) -> RunState {
+        if runstate == RunState::NextLevel {
+            self.goto_next_level();
+            return RunState::PreRun;
+        }
+
+        let next = match runstate {
+            RunState::PreRun => RunState::AwaitingInput,
+            RunState::PlayerTurn => RunState::Ticking,
+            RunState::Ticking => RunState::AwaitingInput,
+            _ => return runstate
+        };
+
+        self.run_systems();
+        // keep scheduling rounds until the player has the energy for their next action
+        if runstate == RunState::Ticking {
+            let mut rounds = 0;
+            while !self.player_ready() && self.runstate() != RunState::GameOver {
+                if rounds >= MAX_ROUNDS_PER_TURN {
+                    rltk::console::log(format!("WARNING - the player still can't act after {} rounds, handing back control", rounds));
+                    break;
+                }
+                self.run_systems();
+                rounds += 1;
+            }
+        }
+
+        // the damage system flags the player's death directly on the RunState resource
+        if self.runstate() == RunState::GameOver {
+            return RunState::GameOver;
+        }
+        if runstate == RunState::PlayerTurn && self.level_cleared() {
+            return RunState::Victory;
+        }
+        // hold off until the monsters are done, so levelling up never interrupts a turn
+        if next == RunState::AwaitingInput && self.level_up_pending() {
+            return RunState::LevelUp;
+        }
+        next
+    }
+
+    fn player_ready(&self) -> bool {
+        let player_entity = self.ecs.fetch::<Entity>();
+        match self.ecs.read_storage::<Energy>().get(*player_entity) {
+            Some(energy) => energy.energy >= ACTION_COST,
+            None => true
+        }
+    }
+
+    pub fn level_up_pending(&self) -> bool {
+        let player_entity = self.ecs.fetch::<Entity>();
+        self.ecs.read_storage::<Experience>().get(*player_entity).is_some_and(|exp| exp.level_up_pending())
+    }
+
+    /// The run is won once nothing hostile is left alive on the final depth. Companions and
+    /// anything that wouldn't attack the player on sight don't count.
+    fn level_cleared(&self) -> bool {
+        if self.ecs.fetch::<Map>().depth < FINAL_DEPTH { return false; }
+
+        let player_entity = self.ecs.fetch::<Entity>();
+        let raws = self.ecs.fetch::<raws::RawMaster>();
+        let monsters = self.ecs.read_storage::<Monster>();
+        let allies = self.ecs.read_storage::<Ally>();
+        let factions = self.ecs.read_storage::<Faction>();
+        let combat_stats = self.ecs.read_storage::<CombatStats>();
+        let player_faction = factions.get(*player_entity).map(|f| f.name.as_str()).unwrap_or("Player");
+
+        !(&monsters, &combat_stats, !&allies, factions.maybe()).join().any(|(_monster, stats, _ally, faction)| {
+            stats.hp > 0 && match faction {
+                Some(faction) => raws.faction_reaction(&faction.name, player_faction) == raws::Reaction::Attack,
+                None => true
+            }
+        })
+    }
+
+    /// Everything except the player and whatever they're carrying.
+    fn entities_to_remove_on_level_change(&mut self) -> Vec<Entity> {
+        let entities = self.ecs.entities();
+        let player = self.ecs.read_storage::<Player>();
+        let backpack = self.ecs.read_storage::<InBackpack>();
+        let equipped = self.ecs.read_storage::<Equipped>();
+        let status_effects = self.ecs.read_storage::<StatusEffect>();
+        let allies = self.ecs.read_storage::<Ally>();
+        let player_entity = self.ecs.fetch::<Entity>();
+        let in_party = |entity: Entity| entity == *player_entity || allies.get(entity).is_some();
+
+        let mut to_delete: Vec<Entity> = Vec::new();
+        for entity in entities.join() {
+            let is_party = player.get(entity).is_some() || in_party(entity);
+            let carried_by_party = backpack.get(entity).is_some_and(|bp| in_party(bp.owner));
+            let worn_by_party = equipped.get(entity).is_some_and(|eq| in_party(eq.owner));
+            let affects_party = status_effects.get(entity).is_some_and(|se| in_party(se.target));
+            if !is_party && !carried_by_party && !worn_by_party && !affects_party {
+                to_delete.push(entity);
+            }
+        }
+        to_delete
+    }
+
+    /// Puts any allies that came down with the player on the free floor nearest to them.
+    fn place_allies(&mut self) {
+        let entities = self.ecs.entities();
+        let allies = self.ecs.read_storage::<Ally>();
+        let blockers = self.ecs.read_storage::<BlocksTile>();
+        let mut positions = self.ecs.write_storage::<Position>();
+        let mut viewsheds = self.ecs.write_storage::<Viewshed>();
+        let mut brains = self.ecs.write_storage::<Brain>();
+        let map = self.ecs.fetch::<Map>();
+        let player_pos = *self.ecs.fetch::<Point>();
+
+        let party: Vec<Entity> = (&entities, &allies).join().map(|(entity, _ally)| entity).collect();
+        let mut taken: Vec<(i32, i32)> = (&entities, &positions, &blockers).join()
+            .filter(|(entity, _pos, _blocker)| !party.contains(entity))
+            .map(|(_entity, pos, _blocker)| (pos.x, pos.y))
+            .collect();
+        taken.push((player_pos.x, player_pos.y));
+
+        for ally in party.iter() {
+            let mut spot: Option<(i32, i32)> = None;
+            'search: for radius in 1..10 {
+                for y in player_pos.y - radius ..= player_pos.y + radius {
+                    for x in player_pos.x - radius ..= player_pos.x + radius {
+                        if x < 1 || x > map.width - 2 || y < 1 || y > map.height - 2 { continue; }
+                        if map.tiles[map.xy_idx(x, y)] != TileType::Floor || taken.contains(&(x, y)) { continue; }
+                        spot = Some((x, y));
+                        break 'search;
+                    }
+                }
+            }
+
+            // no room nearby, so it gets left behind
+            let (x, y) = match spot { Some(spot) => spot, None => { entities.delete(*ally).expect("Unable to delete"); continue; } };
+            taken.push((x, y));
+            if let Some(pos) = positions.get_mut(*ally) {
+                pos.x = x;
+                pos.y = y;
+            }
+            if let Some(viewshed) = viewsheds.get_mut(*ally) {
+                viewshed.dirty = true;
+            }
+            if let Some(brain) = brains.get_mut(*ally) {
+                brain.mode = AiMode::Follow;
+                brain.home_x = x;
+                brain.home_y = y;
+            }
+        }
+    }
+
+    fn goto_next_level(&mut self) {
+        let to_delete = self.entities_to_remove_on_level_change();
+        for target in to_delete {
+            self.ecs.delete_entity(target).expect("Unable to delete entity");
+        }
+
+        let current_depth = self.ecs.fetch::<Map>().depth;
+        self.generate_world_map(current_depth + 1);
+        self.place_allies();
+
+        self.ecs.write_resource::<events::EventQueue>().emit(events::GameEvent::LevelEntered { depth: current_depth + 1 });
+        let player_entity = self.ecs.fetch::<Entity>();
+        let mut player_health_store = self.ecs.write_storage::<CombatStats>();
+        if let Some(player_health) = player_health_store.get_mut(*player_entity) {
+            player_health.hp = i32::max(player_health.hp, player_health.max_hp / 2);
+        }
+    }
+
+    /// Swaps the world for a brand new run, unless the current one hasn't been played yet
+    /// (so the seed picked on the command line is what the first game uses).
+    fn new_run(&mut self) {
+        if self.ecs.fetch::<replay::ReplayRecorder>().actions.is_empty() { return; }
+
+        let seed = rltk::RandomNumberGenerator::new().next_u64();
+        let builder = self.ecs.fetch::<map_builders::BuilderChoice>().kind;
+        let replay_dir = self.ecs.fetch::<replay::ReplayRecorder>().dir.clone();
+        let (show_mapgen, show_ai_debug, show_system_timings, parallel_systems) =
+            (self.show_mapgen, self.show_ai_debug, self.show_system_timings, self.parallel_systems);
+        let raws = self.ecs.remove::<raws::RawMaster>().expect("Raws are always loaded");
+        *self = State::new_game(seed, builder, show_mapgen, raws);
+        self.show_ai_debug = show_ai_debug;
+        self.show_system_timings = show_system_timings;
+        self.parallel_systems = parallel_systems;
+        if let Some(dir) = replay_dir {
+            replay::start_recording(&mut self.ecs, &dir);
+        }
+    }
+
+    pub fn runstate(&self) -> RunState {
+        *self.ecs.fetch::<RunState>()
+    }
+
+    pub fn set_runstate(&mut self, runstate: RunState) {
+        let mut runwriter = self.ecs.write_resource::<RunState>();
+        *runwriter = runstate;
+    }
+}
+
+
+impl GameState for State {
+    fn tick(&mut self, ctx : &mut Rltk) {
+        ctx.cls();
+
+        let mut newrunstate = self.runstate();
+
+        match newrunstate {
+            RunState::MainMenu{..} | RunState::GameOver | RunState::Victory | RunState::MapGeneration => {}
+            _ => {
+                draw_map(&self.ecs.fetch::<Map>(), ctx);
+
+                let positions = self.ecs.read_storage::<Position>();
+                let renderables = self.ecs.read_storage::<Renderable>();
+                let hidden = self.ecs.read_storage::<Hidden>();
+                let map = self.ecs.fetch::<Map>();
+
+                let mut data = (&positions, &renderables, !&hidden).join().collect::<Vec<_>>();
+                data.sort_by(|&a, &b| b.1.render_order.cmp(&a.1.render_order) );
+                for (pos, render, _hidden) in data.iter() {
+                    let idx = map.xy_idx(pos.x, pos.y);
+                    if map.visible_tiles[idx] { ctx.set(pos.x, pos.y, render.fg, render.bg, render.glyph) }
+                }
+                gui::draw_ui(&self.ecs, ctx);
+                if self.show_ai_debug { gui::draw_ai_debug(&self.ecs, ctx); }
+                if self.show_system_timings { gui::draw_system_timings(&self.system_timings.snapshot(), ctx); }
+            }
+        }
+
+        match newrunstate {
+            RunState::PreRun |
+            RunState::PlayerTurn |
+            RunState::Ticking => {
+                newrunstate = self.advance(newrunstate);
+            }
+            RunState::NextLevel => {
+                newrunstate = self.advance(newrunstate);
+                if self.show_mapgen { newrunstate = RunState::MapGeneration; }
+            }
+            RunState::MapGeneration => {
+                // any key skips the rest of the playback
+                if self.mapgen_index >= self.mapgen_history.len() || ctx.key.is_some() {
+                    newrunstate = RunState::PreRun;
+                } else {
+                    draw_map(&self.mapgen_history[self.mapgen_index], ctx);
+
+                    self.mapgen_timer += ctx.frame_time_ms;
+                    if self.mapgen_timer > 200.0 {
+                        self.mapgen_timer = 0.0;
+                        self.mapgen_index += 1;
+                    }
+                }
+            }
+            RunState::AwaitingInput => {
+                newrunstate = match replay::next_playback_action(&mut self.ecs) {
+                    Some(action) => self.perform(action),
+                    None => player_input(self, ctx)
+                }
+            }
+            RunState::LevelUp => {
+                newrunstate = match replay::next_playback_action(&mut self.ecs) {
+                    Some(action) => self.perform(action),
+                    None => match gui::level_up(self, ctx) {
+                        gui::LevelUpResult::NoSelection => RunState::LevelUp,
+                        gui::LevelUpResult::Selected{ choice } => self.perform(Action::LevelUp{ choice })
+                    }
+                }
+            }
+            RunState::ShowCharacterSheet => {
+                if gui::character_sheet(&self.ecs, ctx) == gui::CharacterSheetResult::Close {
+                    newrunstate = RunState::AwaitingInput;
+                }
+            }
+            RunState::ShowInventory => {
+                let result = gui::show_inventory(self, ctx);
+                match result.0 {
+                    gui::ItemMenuResult::Cancel => newrunstate = RunState::AwaitingInput,
+                    gui::ItemMenuResult::NoResponse => {},
+                    gui::ItemMenuResult::Selected => {
+                        let item_entity = result.1.unwrap();
+                        let range = self.ecs.read_storage::<Ranged>().get(item_entity).map(|r| r.range);
+                        if let Some(range) = range {
+                            newrunstate = RunState::ShowTargetting { range, item: item_entity }
+                        } else {
+                            newrunstate = self.perform(Action::UseItem { item: item_entity, target: None });
+                        }
+                    }
+                }
+            }
+            RunState::ShowDropItem => {
+                let result = gui::drop_item_menu(self, ctx);
+                match result.0 {
+                    gui::ItemMenuResult::Cancel => newrunstate = RunState::AwaitingInput,
+                    gui::ItemMenuResult::NoResponse => {}
+                    gui::ItemMenuResult::Selected => {
+                        let item_entity = result.1.unwrap();
+                        newrunstate = self.perform(Action::DropItem { item: item_entity });
+                    }
+                }
+            }
+            RunState::ShowTargetting { range, item } => {
+                let target = gui::ranged_target(self, ctx, range);
+                match target.0 {
+                    gui::ItemMenuResult::Cancel => newrunstate = RunState::AwaitingInput,
+                    gui::ItemMenuResult::NoResponse => {}
+                    gui::ItemMenuResult::Selected => {
+                        newrunstate = self.perform(Action::UseItem { item, target: target.1 });
+                    }
+                }
+            }
+            RunState::MainMenu{ .. } => {
+                let result = gui::main_menu(self, ctx);
+                match result {
+                    gui::MainMenuResult::NoSelection{ selected } => newrunstate = RunState::MainMenu{ menu_selection: selected },
+                    gui::MainMenuResult::Selected{ selected } => {
+                        match selected {
+                            gui::MainMenuSelection::NewGame => {
+                                self.new_run();
+                                newrunstate = if self.show_mapgen { RunState::MapGeneration } else { RunState::PreRun };
+                            }
+                            gui::MainMenuSelection::Quit => ctx.quit(),
+                            gui::MainMenuSelection::LoadGame => {
+                                match saveload_system::load_game(&mut self.ecs) {
+                                    Ok(()) => {
+                                        saveload_system::delete_save();
+                                        // the restored run carries its own history, so rewrite the replay to match
+                                        let replay_dir = self.ecs.fetch::<replay::ReplayRecorder>().dir.clone();
+                                        if let Some(dir) = replay_dir {
+                                            replay::start_recording(&mut self.ecs, &dir);
+                                        }
+                                    }
+                                    Err(e) => {
+                                        rltk::console::log(format!("WARNING - {}", e));
+                                        self.new_run();
+                                        self.ecs.write_resource::<gamelog::GameLog>().entries.push("Your save couldn't be loaded.".to_string());
+                                    }
+                                }
+                                newrunstate = RunState::PreRun;
+                            }
+                        }
+                    }
+                }
+            }
+            RunState::GameOver => {
+                if gui::game_over(&self.ecs, ctx) == gui::GameOverResult::QuitToMenu {
+                    newrunstate = RunState::MainMenu{ menu_selection: gui::MainMenuSelection::NewGame };
+                }
+            }
+            RunState::Victory => {
+                if gui::victory(&self.ecs, ctx) == gui::GameOverResult::QuitToMenu {
+                    newrunstate = RunState::MainMenu{ menu_selection: gui::MainMenuSelection::NewGame };
+                }
+            }
+            RunState::SaveGame => {
+                saveload_system::save_game(&mut self.ecs);
+                ctx.quit();
+                newrunstate = RunState::AwaitingInput;
+            }
+        }
+    
+        self.set_runstate(newrunstate);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allies_follow_the_player_down() {
+        let mut gs = State::new_game(1, None, false, raws::load_raws().unwrap());
+        let start = *gs.ecs.fetch::<Point>();
+        let mercenary = raws::spawn_named_entity(&mut gs.ecs, "Mercenary", start.x + 1, start.y).unwrap();
+        let goblin = raws::spawn_named_entity(&mut gs.ecs, "Goblin", start.x, start.y + 1).unwrap();
+        let potion = raws::spawn_named_entity(&mut gs.ecs, "Health Potion", 0, 0).unwrap();
+        gs.ecs.write_storage::<Position>().remove(potion);
+        gs.ecs.write_storage::<InBackpack>().insert(potion, InBackpack { owner: mercenary }).unwrap();
+
+        gs.goto_next_level();
+        gs.ecs.maintain();
+
+        assert_eq!(gs.ecs.fetch::<Map>().depth, 2);
+        assert!(!gs.ecs.is_alive(goblin), "only the party comes along");
+        assert!(gs.ecs.is_alive(mercenary));
+        assert!(gs.ecs.is_alive(potion), "what an ally carries comes with it");
+
+        let player_pos = *gs.ecs.fetch::<Point>();
+        let pos = gs.ecs.read_storage::<Position>().get(mercenary).unwrap().clone();
+        let map = gs.ecs.fetch::<Map>();
+        assert!(map.tiles[map.xy_idx(pos.x, pos.y)] == TileType::Floor);
+        assert!((pos.x, pos.y) != (player_pos.x, player_pos.y));
+        assert!((pos.x - player_pos.x).abs() < 10 && (pos.y - player_pos.y).abs() < 10);
+        let brains = gs.ecs.read_storage::<Brain>();
+        let brain = brains.get(mercenary).unwrap();
+        assert_eq!(brain.mode, AiMode::Follow);
+        assert_eq!((brain.home_x, brain.home_y), (pos.x, pos.y));
+    }
+}
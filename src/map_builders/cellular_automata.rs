@@ -0,0 +1,98 @@
+use rltk::RandomNumberGenerator;
+use std::collections::BTreeMap;
+use specs::prelude::*;
+use super::{MapBuilder, Map, Position, SnapshotHistory, nearest_floor, remove_unreachable_areas_returning_most_distant, generate_voronoi_spawn_regions, place_down_stairs};
+use crate::{TileType, spawner};
+
+/// Starts from noise and smooths it over and over until it settles into caves.
+pub struct CellularAutomataBuilder {
+    map: Map,
+    starting_position: Position,
+    history: SnapshotHistory,
+    noise_areas: BTreeMap<usize, Vec<usize>>,
+}
+
+impl MapBuilder for CellularAutomataBuilder {
+    fn build_map(&mut self, rng: &mut RandomNumberGenerator) {
+        // roughly 55% floor to start with
+        for y in 1..self.map.height-1 {
+            for x in 1..self.map.width-1 {
+                let roll = rng.roll_dice(1, 100);
+                let idx = self.map.xy_idx(x, y);
+                if roll > 55 { self.map.tiles[idx] = TileType::Floor }
+                else { self.map.tiles[idx] = TileType::Wall }
+            }
+        }
+        self.take_snapshot();
+
+        // a tile becomes wall if it's crowded by walls or completely alone
+        for _i in 0..15 {
+            let mut newtiles = self.map.tiles.clone();
+
+            for y in 1..self.map.height-1 {
+                for x in 1..self.map.width-1 {
+                    let idx = self.map.xy_idx(x, y);
+                    let mut neighbors = 0;
+                    for dy in -1 ..= 1 {
+                        for dx in -1 ..= 1 {
+                            if dx == 0 && dy == 0 { continue; }
+                            if self.map.tiles[self.map.xy_idx(x + dx, y + dy)] == TileType::Wall { neighbors += 1; }
+                        }
+                    }
+
+                    if neighbors > 4 || neighbors == 0 {
+                        newtiles[idx] = TileType::Wall;
+                    } else {
+                        newtiles[idx] = TileType::Floor;
+                    }
+                }
+            }
+
+            self.map.tiles = newtiles;
+            self.take_snapshot();
+        }
+
+        let (start_x, start_y) = nearest_floor(&self.map, self.map.width / 2, self.map.height / 2);
+        self.starting_position = Position { x: start_x, y: start_y };
+        let start_idx = self.map.xy_idx(start_x, start_y);
+
+        let exit_idx = remove_unreachable_areas_returning_most_distant(&mut self.map, start_idx);
+        place_down_stairs(&mut self.map, exit_idx);
+        self.take_snapshot();
+
+        self.noise_areas = generate_voronoi_spawn_regions(&self.map, start_idx, rng);
+    }
+
+    fn spawn_entities(&mut self, ecs: &mut World) {
+        for area in self.noise_areas.values() {
+            spawner::spawn_region(ecs, area, self.map.depth);
+        }
+    }
+
+    fn get_starting_position(&self) -> Position {
+        self.starting_position.clone()
+    }
+
+    fn map(&self) -> &Map {
+        &self.map
+    }
+
+    fn history(&self) -> &SnapshotHistory {
+        &self.history
+    }
+
+    fn history_mut(&mut self) -> &mut SnapshotHistory {
+        &mut self.history
+    }
+}
+
+impl CellularAutomataBuilder {
+    pub fn new(new_depth: i32, record_history: bool) -> CellularAutomataBuilder {
+        CellularAutomataBuilder {
+            map: Map::new(new_depth),
+            starting_position: Position { x: 0, y: 0 },
+            history: SnapshotHistory::new(record_history),
+            noise_areas: BTreeMap::new(),
+        }
+    }
+}
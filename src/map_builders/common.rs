@@ -0,0 +1,156 @@
+use rltk::RandomNumberGenerator;
+use std::cmp::{max, min};
+use std::collections::BTreeMap;
+use crate::{Map, Rect, TileType, FINAL_DEPTH};
+
+pub fn apply_room_to_map(map: &mut Map, room: &Rect) {
+    for y in room.y1 +1 ..= room.y2 {
+        for x in room.x1 + 1 ..= room.x2 {
+            let idx = map.xy_idx(x, y);
+            map.tiles[idx] = TileType::Floor;
+        }
+    }
+}
+
+pub fn apply_horizontal_tunnel(map: &mut Map, x1:i32, x2:i32, y:i32) {
+    for x in min(x1,x2) ..= max(x1,x2) {
+        let idx = map.xy_idx(x, y);
+        if idx > 0 && idx < map.width as usize * map.height as usize {
+            map.tiles[idx] = TileType::Floor;
+        }
+    }
+}
+
+pub fn apply_vertical_tunnel(map: &mut Map, y1:i32, y2:i32, x:i32) {
+    for y in min(y1,y2) ..= max(y1,y2) {
+        let idx = map.xy_idx(x, y);
+        if idx > 0 && idx < map.width as usize * map.height as usize {
+            map.tiles[idx] = TileType::Floor;
+        }
+    }
+}
+
+/// Tiles just outside a room's walls where a corridor comes in through a one tile gap, for doors to go in.
+pub fn find_door_places(map: &Map) -> Vec<usize> {
+    let mut doors: Vec<usize> = Vec::new();
+    let is_wall = |x: i32, y: i32| map.tiles[map.xy_idx(x, y)] == TileType::Wall;
+    for room in map.rooms.iter() {
+        // the room's floor runs from x1+1..=x2 and y1+1..=y2, so its edge is one tile further out
+        for x in room.x1 + 1 ..= room.x2 {
+            for y in [room.y1, room.y2 + 1].iter() {
+                let idx = map.xy_idx(x, *y);
+                if map.tiles[idx] == TileType::Floor && is_wall(x - 1, *y) && is_wall(x + 1, *y) {
+                    doors.push(idx);
+                }
+            }
+        }
+        for y in room.y1 + 1 ..= room.y2 {
+            for x in [room.x1, room.x2 + 1].iter() {
+                let idx = map.xy_idx(*x, y);
+                if map.tiles[idx] == TileType::Floor && is_wall(*x, y - 1) && is_wall(*x, y + 1) {
+                    doors.push(idx);
+                }
+            }
+        }
+    }
+    doors.sort_unstable();
+    doors.dedup();
+    doors
+}
+
+/// A copy of the map with everything revealed, for the generation visualizer.
+pub fn snapshot_of(map: &Map) -> Map {
+    let mut snapshot = map.clone();
+    for v in snapshot.revealed_tiles.iter_mut() { *v = true; }
+    for v in snapshot.visible_tiles.iter_mut() { *v = true; }
+    snapshot
+}
+
+/// Every step a builder went through, kept only when it was asked to record them.
+pub struct SnapshotHistory {
+    pub(super) snapshots: Vec<Map>,
+    pub(super) recording: bool,
+}
+
+impl SnapshotHistory {
+    pub fn new(recording: bool) -> SnapshotHistory {
+        SnapshotHistory { snapshots: Vec::new(), recording }
+    }
+}
+
+/// Puts the way down at `idx`, unless this is already the bottom of the dungeon.
+pub fn place_down_stairs(map: &mut Map, idx: usize) {
+    if map.depth < FINAL_DEPTH {
+        map.tiles[idx] = TileType::DownStairs;
+    }
+}
+
+/// Finds the floor tile closest to `(x, y)`, searching outward in rings.
+pub fn nearest_floor(map: &Map, x: i32, y: i32) -> (i32, i32) {
+    for radius in 0..map.width {
+        for dy in -radius ..= radius {
+            for dx in -radius ..= radius {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 1 || nx > map.width-2 || ny < 1 || ny > map.height-2 { continue; }
+                if map.tiles[map.xy_idx(nx, ny)] == TileType::Floor {
+                    return (nx, ny);
+                }
+            }
+        }
+    }
+    (x, y)
+}
+
+/// Walls over anything that can't be walked to from the start and returns the reachable tile furthest from it.
+pub fn remove_unreachable_areas_returning_most_distant(map: &mut Map, start_idx: usize) -> usize {
+    map.populate_blocked();
+    let map_starts: Vec<usize> = vec![start_idx];
+    let dijkstra_map = rltk::DijkstraMap::new(map.width as usize, map.height as usize, &map_starts, map, 200.0);
+    let mut exit_tile = (0, 0.0f32);
+    for (i, tile) in map.tiles.iter_mut().enumerate() {
+        if *tile == TileType::Floor {
+            let distance_to_start = dijkstra_map.map[i];
+            if distance_to_start == f32::MAX {
+                *tile = TileType::Wall;
+            } else if distance_to_start > exit_tile.1 {
+                exit_tile.0 = i;
+                exit_tile.1 = distance_to_start;
+            }
+        }
+    }
+
+    exit_tile.0
+}
+
+/// Splits the floor into Voronoi cells around random seed points, so maps without rooms
+/// still get their monsters and items spread around in clumps. `start_idx` is left out of
+/// every region so nothing gets dropped on top of the player.
+pub fn generate_voronoi_spawn_regions(map: &Map, start_idx: usize, rng: &mut RandomNumberGenerator) -> BTreeMap<usize, Vec<usize>> {
+    const SEED_COUNT: usize = 32;
+
+    let mut seeds: Vec<(i32, i32)> = Vec::new();
+    for _i in 0..SEED_COUNT {
+        seeds.push((rng.roll_dice(1, map.width-2), rng.roll_dice(1, map.height-2)));
+    }
+
+    let mut regions: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for y in 1 .. map.height-1 {
+        for x in 1 .. map.width-1 {
+            let idx = map.xy_idx(x, y);
+            if map.tiles[idx] != TileType::Floor || idx == start_idx { continue; }
+
+            let mut closest = 0;
+            let mut closest_distance = i32::MAX;
+            for (i, seed) in seeds.iter().enumerate() {
+                let distance = (seed.0 - x) * (seed.0 - x) + (seed.1 - y) * (seed.1 - y);
+                if distance < closest_distance {
+                    closest = i;
+                    closest_distance = distance;
+                }
+            }
+            regions.entry(closest).or_default().push(idx);
+        }
+    }
+
+    regions
+}
@@ -0,0 +1,101 @@
+use rltk::RandomNumberGenerator;
+use std::collections::BTreeMap;
+use specs::prelude::*;
+use super::{MapBuilder, Map, Position, SnapshotHistory, remove_unreachable_areas_returning_most_distant, generate_voronoi_spawn_regions, place_down_stairs};
+use crate::{TileType, spawner};
+
+/// Diffusion-limited aggregation: diggers wander in from random points and stick to the first floor they touch,
+/// growing branching tunnels out from the middle.
+pub struct DlaBuilder {
+    map: Map,
+    starting_position: Position,
+    history: SnapshotHistory,
+    noise_areas: BTreeMap<usize, Vec<usize>>,
+}
+
+impl MapBuilder for DlaBuilder {
+    fn build_map(&mut self, rng: &mut RandomNumberGenerator) {
+        self.starting_position = Position { x: self.map.width / 2, y: self.map.height / 2 };
+        let start_idx = self.map.xy_idx(self.starting_position.x, self.starting_position.y);
+
+        // a small seed for everything else to stick to
+        for dy in -1 ..= 1 {
+            for dx in -1 ..= 1 {
+                let idx = self.map.xy_idx(self.starting_position.x + dx, self.starting_position.y + dy);
+                self.map.tiles[idx] = TileType::Floor;
+            }
+        }
+
+        let total_tiles = self.map.width * self.map.height;
+        let desired_floor_tiles = (total_tiles / 4) as usize;
+        let mut floor_tile_count = self.map.tiles.iter().filter(|t| **t == TileType::Floor).count();
+
+        while floor_tile_count < desired_floor_tiles {
+            let mut digger_x = rng.roll_dice(1, self.map.width - 3) + 1;
+            let mut digger_y = rng.roll_dice(1, self.map.height - 3) + 1;
+            let mut prev_x = digger_x;
+            let mut prev_y = digger_y;
+            let mut digger_idx = self.map.xy_idx(digger_x, digger_y);
+
+            while self.map.tiles[digger_idx] == TileType::Wall {
+                prev_x = digger_x;
+                prev_y = digger_y;
+                match rng.roll_dice(1, 4) {
+                    1 => { if digger_x > 2 { digger_x -= 1; } }
+                    2 => { if digger_x < self.map.width-2 { digger_x += 1; } }
+                    3 => { if digger_y > 2 { digger_y -= 1; } }
+                    _ => { if digger_y < self.map.height-2 { digger_y += 1; } }
+                }
+                digger_idx = self.map.xy_idx(digger_x, digger_y);
+            }
+
+            let prev_idx = self.map.xy_idx(prev_x, prev_y);
+            self.map.tiles[prev_idx] = TileType::Floor;
+
+            floor_tile_count = self.map.tiles.iter().filter(|t| **t == TileType::Floor).count();
+            // one frame per stuck digger would take minutes to watch
+            if floor_tile_count % 10 == 0 {
+                self.take_snapshot();
+            }
+        }
+
+        let exit_idx = remove_unreachable_areas_returning_most_distant(&mut self.map, start_idx);
+        place_down_stairs(&mut self.map, exit_idx);
+        self.take_snapshot();
+
+        self.noise_areas = generate_voronoi_spawn_regions(&self.map, start_idx, rng);
+    }
+
+    fn spawn_entities(&mut self, ecs: &mut World) {
+        for area in self.noise_areas.values() {
+            spawner::spawn_region(ecs, area, self.map.depth);
+        }
+    }
+
+    fn get_starting_position(&self) -> Position {
+        self.starting_position.clone()
+    }
+
+    fn map(&self) -> &Map {
+        &self.map
+    }
+
+    fn history(&self) -> &SnapshotHistory {
+        &self.history
+    }
+
+    fn history_mut(&mut self) -> &mut SnapshotHistory {
+        &mut self.history
+    }
+}
+
+impl DlaBuilder {
+    pub fn new(new_depth: i32, record_history: bool) -> DlaBuilder {
+        DlaBuilder {
+            map: Map::new(new_depth),
+            starting_position: Position { x: 0, y: 0 },
+            history: SnapshotHistory::new(record_history),
+            noise_areas: BTreeMap::new(),
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use rltk::RandomNumberGenerator;
+use std::collections::BTreeMap;
+use specs::prelude::*;
+use super::{MapBuilder, Map, Position, SnapshotHistory, remove_unreachable_areas_returning_most_distant, generate_voronoi_spawn_regions, place_down_stairs};
+use crate::{TileType, spawner};
+
+/// Lets diggers stumble around at random, carving floor wherever they step, until half the map is open.
+pub struct DrunkardsWalkBuilder {
+    map: Map,
+    starting_position: Position,
+    history: SnapshotHistory,
+    noise_areas: BTreeMap<usize, Vec<usize>>,
+}
+
+impl MapBuilder for DrunkardsWalkBuilder {
+    fn build_map(&mut self, rng: &mut RandomNumberGenerator) {
+        const DRUNKEN_LIFETIME: i32 = 400;
+
+        self.starting_position = Position { x: self.map.width / 2, y: self.map.height / 2 };
+        let start_idx = self.map.xy_idx(self.starting_position.x, self.starting_position.y);
+        self.map.tiles[start_idx] = TileType::Floor;
+
+        let total_tiles = self.map.width * self.map.height;
+        let desired_floor_tiles = (total_tiles / 2) as usize;
+        let mut floor_tile_count = 1;
+        let mut digger_count = 0;
+
+        while floor_tile_count < desired_floor_tiles {
+            // the first digger starts where the player will, the rest start anywhere already dug
+            let (mut drunk_x, mut drunk_y) = if digger_count == 0 {
+                (self.starting_position.x, self.starting_position.y)
+            } else {
+                let floors: Vec<usize> = self.map.tiles.iter().enumerate()
+                    .filter(|(_, t)| **t == TileType::Floor)
+                    .map(|(i, _)| i)
+                    .collect();
+                let idx = floors[(rng.roll_dice(1, floors.len() as i32)-1) as usize];
+                (idx as i32 % self.map.width, idx as i32 / self.map.width)
+            };
+
+            for _step in 0..DRUNKEN_LIFETIME {
+                let drunk_idx = self.map.xy_idx(drunk_x, drunk_y);
+                self.map.tiles[drunk_idx] = TileType::Floor;
+
+                match rng.roll_dice(1, 4) {
+                    1 => { if drunk_x > 2 { drunk_x -= 1; } }
+                    2 => { if drunk_x < self.map.width-2 { drunk_x += 1; } }
+                    3 => { if drunk_y > 2 { drunk_y -= 1; } }
+                    _ => { if drunk_y < self.map.height-2 { drunk_y += 1; } }
+                }
+            }
+
+            self.take_snapshot();
+            digger_count += 1;
+            floor_tile_count = self.map.tiles.iter().filter(|t| **t == TileType::Floor).count();
+        }
+
+        let exit_idx = remove_unreachable_areas_returning_most_distant(&mut self.map, start_idx);
+        place_down_stairs(&mut self.map, exit_idx);
+        self.take_snapshot();
+
+        self.noise_areas = generate_voronoi_spawn_regions(&self.map, start_idx, rng);
+    }
+
+    fn spawn_entities(&mut self, ecs: &mut World) {
+        for area in self.noise_areas.values() {
+            spawner::spawn_region(ecs, area, self.map.depth);
+        }
+    }
+
+    fn get_starting_position(&self) -> Position {
+        self.starting_position.clone()
+    }
+
+    fn map(&self) -> &Map {
+        &self.map
+    }
+
+    fn history(&self) -> &SnapshotHistory {
+        &self.history
+    }
+
+    fn history_mut(&mut self) -> &mut SnapshotHistory {
+        &mut self.history
+    }
+}
+
+impl DrunkardsWalkBuilder {
+    pub fn new(new_depth: i32, record_history: bool) -> DrunkardsWalkBuilder {
+        DrunkardsWalkBuilder {
+            map: Map::new(new_depth),
+            starting_position: Position { x: 0, y: 0 },
+            history: SnapshotHistory::new(record_history),
+            noise_areas: BTreeMap::new(),
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use rltk::RandomNumberGenerator;
+use std::collections::BTreeMap;
+use specs::prelude::*;
+use super::{MapBuilder, Map, Position, SnapshotHistory, remove_unreachable_areas_returning_most_distant, generate_voronoi_spawn_regions, place_down_stairs};
+use crate::{TileType, spawner};
+
+/// Recursive backtracker: carves a perfect maze on a grid of cells two tiles apart.
+pub struct MazeBuilder {
+    map: Map,
+    starting_position: Position,
+    history: SnapshotHistory,
+    noise_areas: BTreeMap<usize, Vec<usize>>,
+}
+
+impl MapBuilder for MazeBuilder {
+    fn build_map(&mut self, rng: &mut RandomNumberGenerator) {
+        // cell (cx, cy) sits on tile (cx*2+1, cy*2+1), with the walls between cells on the even tiles
+        let cells_wide = (self.map.width - 1) / 2;
+        let cells_high = (self.map.height - 1) / 2;
+        let mut visited = vec![false; (cells_wide * cells_high) as usize];
+        let mut stack: Vec<(i32, i32)> = vec![(0, 0)];
+        visited[0] = true;
+        let start_idx = self.map.xy_idx(1, 1);
+        self.map.tiles[start_idx] = TileType::Floor;
+
+        while let Some(&(cx, cy)) = stack.last() {
+            let mut neighbours: Vec<(i32, i32)> = Vec::new();
+            for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)].iter() {
+                let (nx, ny) = (cx + dx, cy + dy);
+                if nx >= 0 && nx < cells_wide && ny >= 0 && ny < cells_high && !visited[(ny * cells_wide + nx) as usize] {
+                    neighbours.push((*dx, *dy));
+                }
+            }
+
+            if neighbours.is_empty() {
+                stack.pop();
+                continue;
+            }
+
+            let (dx, dy) = neighbours[(rng.roll_dice(1, neighbours.len() as i32)-1) as usize];
+            let (nx, ny) = (cx + dx, cy + dy);
+            let wall_idx = self.map.xy_idx(cx*2+1 + dx, cy*2+1 + dy);
+            let cell_idx = self.map.xy_idx(nx*2+1, ny*2+1);
+            self.map.tiles[wall_idx] = TileType::Floor;
+            self.map.tiles[cell_idx] = TileType::Floor;
+            visited[(ny * cells_wide + nx) as usize] = true;
+            stack.push((nx, ny));
+            // one frame per cell would take minutes to watch
+            if stack.len() % 10 == 0 {
+                self.take_snapshot();
+            }
+        }
+
+        self.starting_position = Position { x: 1, y: 1 };
+        let exit_idx = remove_unreachable_areas_returning_most_distant(&mut self.map, start_idx);
+        place_down_stairs(&mut self.map, exit_idx);
+        self.take_snapshot();
+
+        self.noise_areas = generate_voronoi_spawn_regions(&self.map, start_idx, rng);
+    }
+
+    fn spawn_entities(&mut self, ecs: &mut World) {
+        for area in self.noise_areas.values() {
+            spawner::spawn_region(ecs, area, self.map.depth);
+        }
+    }
+
+    fn get_starting_position(&self) -> Position {
+        self.starting_position.clone()
+    }
+
+    fn map(&self) -> &Map {
+        &self.map
+    }
+
+    fn history(&self) -> &SnapshotHistory {
+        &self.history
+    }
+
+    fn history_mut(&mut self) -> &mut SnapshotHistory {
+        &mut self.history
+    }
+}
+
+impl MazeBuilder {
+    pub fn new(new_depth: i32, record_history: bool) -> MazeBuilder {
+        MazeBuilder {
+            map: Map::new(new_depth),
+            starting_position: Position { x: 0, y: 0 },
+            history: SnapshotHistory::new(record_history),
+            noise_areas: BTreeMap::new(),
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use rltk::RandomNumberGenerator;
+use specs::prelude::*;
+use serde::{Serialize, Deserialize};
+use super::{Map, Position};
+mod common;
+use common::*;
+mod simple_map;
+use simple_map::SimpleMapBuilder;
+mod bsp_dungeon;
+use bsp_dungeon::BspDungeonBuilder;
+mod cellular_automata;
+use cellular_automata::CellularAutomataBuilder;
+mod drunkard;
+use drunkard::DrunkardsWalkBuilder;
+mod maze;
+use maze::MazeBuilder;
+mod dla;
+use dla::DlaBuilder;
+
+pub trait MapBuilder {
+    fn build_map(&mut self, rng: &mut RandomNumberGenerator);
+    fn spawn_entities(&mut self, ecs: &mut World);
+    fn get_starting_position(&self) -> Position;
+    fn map(&self) -> &Map;
+    fn history(&self) -> &SnapshotHistory;
+    fn history_mut(&mut self) -> &mut SnapshotHistory;
+
+    fn get_map(&self) -> Map {
+        self.map().clone()
+    }
+
+    fn get_snapshot_history(&self) -> Vec<Map> {
+        self.history().snapshots.clone()
+    }
+
+    /// Notes the map as it stands for `--show-mapgen`, does nothing unless the builder was asked to record.
+    fn take_snapshot(&mut self) {
+        if self.history().recording {
+            let snapshot = snapshot_of(self.map());
+            self.history_mut().snapshots.push(snapshot);
+        }
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum BuilderKind { Simple, Bsp, Cellular, Drunkard, Maze, Dla }
+
+impl BuilderKind {
+    /// Parses the name used on the command line, e.g. `--builder cellular`.
+    pub fn from_name(name: &str) -> Option<BuilderKind> {
+        match name {
+            "simple" => Some(BuilderKind::Simple),
+            "bsp" => Some(BuilderKind::Bsp),
+            "cellular" => Some(BuilderKind::Cellular),
+            "drunkard" => Some(BuilderKind::Drunkard),
+            "maze" => Some(BuilderKind::Maze),
+            "dla" => Some(BuilderKind::Dla),
+            _ => None
+        }
+    }
+
+    /// Each depth has its own flavour of level, the final depth is a maze.
+    pub fn for_depth(depth: i32) -> BuilderKind {
+        match depth {
+            1 => BuilderKind::Simple,
+            2 => BuilderKind::Bsp,
+            3 => BuilderKind::Cellular,
+            4 => BuilderKind::Drunkard,
+            5 => BuilderKind::Dla,
+            _ => BuilderKind::Maze
+        }
+    }
+}
+
+/// Forces every level to use one generator, `None` leaves it up to the depth.
+pub struct BuilderChoice {
+    pub kind: Option<BuilderKind>
+}
+
+/// `record_history` keeps a snapshot of every step for the mapgen playback, which is only
+/// worth the copies when someone's going to watch it.
+pub fn builder_for(new_depth: i32, choice: Option<BuilderKind>, record_history: bool) -> Box<dyn MapBuilder> {
+    match choice.unwrap_or_else(|| BuilderKind::for_depth(new_depth)) {
+        BuilderKind::Simple => Box::new(SimpleMapBuilder::new(new_depth, record_history)),
+        BuilderKind::Bsp => Box::new(BspDungeonBuilder::new(new_depth, record_history)),
+        BuilderKind::Cellular => Box::new(CellularAutomataBuilder::new(new_depth, record_history)),
+        BuilderKind::Drunkard => Box::new(DrunkardsWalkBuilder::new(new_depth, record_history)),
+        BuilderKind::Maze => Box::new(MazeBuilder::new(new_depth, record_history)),
+        BuilderKind::Dla => Box::new(DlaBuilder::new(new_depth, record_history)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{State, TileType, raws};
+
+    #[test]
+    fn spawn_regions_leave_out_the_start() {
+        let mut map = Map::new(1);
+        for tile in map.tiles.iter_mut() { *tile = TileType::Floor; }
+        let start_idx = map.xy_idx(map.width / 2, map.height / 2);
+        let mut rng = RandomNumberGenerator::seeded(3);
+        let regions = generate_voronoi_spawn_regions(&map, start_idx, &mut rng);
+        let tiles: usize = regions.values().map(|area| area.len()).sum();
+        assert_eq!(tiles, (map.width as usize - 2) * (map.height as usize - 2) - 1);
+        assert!(regions.values().all(|area| !area.contains(&start_idx)));
+    }
+
+    #[test]
+    fn nothing_spawns_on_the_starting_tile() {
+        for kind in [BuilderKind::Cellular, BuilderKind::Drunkard, BuilderKind::Maze, BuilderKind::Dla].iter() {
+            for seed in 1..10 {
+                let gs = State::new_game(seed, Some(*kind), false, raws::load_raws().unwrap());
+                let player = *gs.ecs.fetch::<Entity>();
+                let positions = gs.ecs.read_storage::<Position>();
+                let start = positions.get(player).unwrap();
+                for (entity, pos) in (&gs.ecs.entities(), &positions).join() {
+                    assert!(entity == player || pos.x != start.x || pos.y != start.y,
+                        "{:?} seed {} put something on the player", kind, seed);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use rltk::RandomNumberGenerator;
+use specs::prelude::*;
+use super::{MapBuilder, Map, Position, SnapshotHistory, find_door_places, apply_room_to_map, apply_horizontal_tunnel, apply_vertical_tunnel, place_down_stairs};
+use crate::{Rect, spawner};
+
+/// This gives a handful of random rooms and corridors joining them together.
+pub struct SimpleMapBuilder {
+    map: Map,
+    starting_position: Position,
+    history: SnapshotHistory,
+}
+
+impl MapBuilder for SimpleMapBuilder {
+    fn build_map(&mut self, rng: &mut RandomNumberGenerator) {
+        const MAX_ROOMS : i32 = 30;
+        const MIN_SIZE : i32 = 6;
+        const MAX_SIZE : i32 = 10;
+
+        for _i in 0..MAX_ROOMS {
+            let w = rng.range(MIN_SIZE, MAX_SIZE);
+            let h = rng.range(MIN_SIZE, MAX_SIZE);
+            let x = rng.roll_dice(1, self.map.width - w - 1) - 1;
+            let y = rng.roll_dice(1, self.map.height - h - 1) - 1;
+            let new_room = Rect::new(x, y, w, h);
+            let mut ok = true;
+            for other_room in self.map.rooms.iter() {
+                if new_room.intersect(other_room) { ok = false }
+            }
+            if ok {
+                apply_room_to_map(&mut self.map, &new_room);
+
+                if !self.map.rooms.is_empty() {
+                    let (new_x, new_y) = new_room.center();
+                    let (prev_x, prev_y) = self.map.rooms[self.map.rooms.len()-1].center();
+                    if rng.range(0,2) == 1 {
+                        apply_horizontal_tunnel(&mut self.map, prev_x, new_x, prev_y);
+                        apply_vertical_tunnel(&mut self.map, prev_y, new_y, new_x);
+                    } else {
+                        apply_vertical_tunnel(&mut self.map, prev_y, new_y, prev_x);
+                        apply_horizontal_tunnel(&mut self.map, prev_x, new_x, new_y);
+                    }
+                }
+
+                self.map.rooms.push(new_room);
+                self.take_snapshot();
+            }
+        }
+
+        let (stairs_x, stairs_y) = self.map.rooms[self.map.rooms.len()-1].center();
+        let stairs_idx = self.map.xy_idx(stairs_x, stairs_y);
+        place_down_stairs(&mut self.map, stairs_idx);
+        self.take_snapshot();
+
+        let (start_x, start_y) = self.map.rooms[0].center();
+        self.starting_position = Position { x: start_x, y: start_y };
+    }
+
+    fn spawn_entities(&mut self, ecs: &mut World) {
+        spawner::spawn_doors(ecs, &self.map, &find_door_places(&self.map));
+        for room in self.map.rooms.iter().skip(1) {
+            spawner::spawn_room(ecs, &self.map, room, self.map.depth);
+        }
+    }
+
+    fn get_starting_position(&self) -> Position {
+        self.starting_position.clone()
+    }
+
+    fn map(&self) -> &Map {
+        &self.map
+    }
+
+    fn history(&self) -> &SnapshotHistory {
+        &self.history
+    }
+
+    fn history_mut(&mut self) -> &mut SnapshotHistory {
+        &mut self.history
+    }
+}
+
+impl SimpleMapBuilder {
+    pub fn new(new_depth: i32, record_history: bool) -> SimpleMapBuilder {
+        SimpleMapBuilder {
+            map: Map::new(new_depth),
+            starting_position: Position { x: 0, y: 0 },
+            history: SnapshotHistory::new(record_history),
+        }
+    }
+}
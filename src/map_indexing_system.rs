@@ -1,5 +1,5 @@
 use specs::prelude::*;
-use super::{Map, Position, BlocksTile};
+use super::{Map, Position, BlocksTile, Door};
 
 pub struct MapIndexingSystem {}
 
@@ -7,13 +7,15 @@ impl<'a> System<'a> for MapIndexingSystem {
     type SystemData = (WriteExpect<'a, Map>,
                         ReadStorage<'a, Position>,
                         ReadStorage<'a, BlocksTile>,
+                        ReadStorage<'a, Door>,
                         Entities<'a>,);
 
     fn run(&mut self, data: Self::SystemData) {
-        let (mut map, position, blockers, entities) = data;
+        let (mut map, position, blockers, doors, entities) = data;
 
         map.populate_blocked();
         map.clear_content_index();
+        for closed in map.closed_doors.iter_mut() { *closed = false; }
         for (entity, position) in (&entities, &position).join() {
             let idx = map.xy_idx(position.x, position.y);
 
@@ -21,6 +23,9 @@ impl<'a> System<'a> for MapIndexingSystem {
             if let Some(_p) = _p {
                 map.blocked[idx] = true;
             }
+            if let Some(door) = doors.get(entity) {
+                map.closed_doors[idx] = !door.open;
+            }
             
             map.tile_content[idx].push(entity);
         }
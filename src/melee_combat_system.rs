@@ -1,46 +1,178 @@
-use super::{CombatStats, Name, SufferDamage, WantsToMelee, gamelog::GameLog };
+use super::{CombatStats, Attributes, Skills, Name, SufferDamage, WantsToMelee, events::{EventQueue, GameEvent, DamageKind}, Equipped, MeleePowerBonus, DefenseBonus, AttackCost, Energy, ACTION_COST, Position, sort_by_position, gamesystem };
 use specs::prelude::*;
+use rltk::{RandomNumberGenerator, DiceType};
+
+/// Attack rolls at or above this always hit and roll their damage dice twice.
+const CRITICAL_ROLL: i32 = 20;
+/// Attack rolls at or below this always miss.
+const FUMBLE_ROLL: i32 = 1;
+/// What an attack roll has to beat before any defense is added.
+const BASE_ARMOR_CLASS: i32 = 10;
 
 pub struct MeleeCombatSystem {}
 
 impl<'a> System<'a> for MeleeCombatSystem {
+    #[allow(clippy::type_complexity)]
     type SystemData = (
         Entities<'a>,
-        WriteExpect<'a, GameLog>,
+        WriteExpect<'a, EventQueue>,
+        WriteExpect<'a, RandomNumberGenerator>,
         WriteStorage<'a, WantsToMelee>,
         ReadStorage<'a, Name>,
         ReadStorage<'a, CombatStats>,
         WriteStorage<'a, SufferDamage>,
+        ReadStorage<'a, Equipped>,
+        ReadStorage<'a, MeleePowerBonus>,
+        ReadStorage<'a, DefenseBonus>,
+        ReadStorage<'a, Attributes>,
+        ReadStorage<'a, Skills>,
+        ReadStorage<'a, AttackCost>,
+        WriteStorage<'a, Energy>,
+        ReadStorage<'a, Position>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (entities, mut log, mut wants_to_melee, names, combat_stats, mut inflict_damage) = data;
-
-        for (_entity, wants_melee, name, stats) in
-            (&entities, &wants_to_melee, &names, &combat_stats).join()
-        {
-            if stats.hp > 0 {
-                let target_stats = combat_stats.get(wants_melee.target).unwrap();
-                if target_stats.hp > 0 {
-                    let target_name = names.get(wants_melee.target).unwrap();
-                
-                    let damage = i32::max(0, stats.power - target_stats.defense);
-
-                    if damage == 0 {
-                        log.entries.push(format!(
-                            "{} is unable to hurt {}",
-                            &name.name, &target_name.name
-                        ));
-                    } else {
-                        log.entries.push(format!(
-                            "{} hits {} for {} hp",
-                            &name.name, &target_name.name, damage
-                        ));
-                        SufferDamage::new_damage(&mut inflict_damage, wants_melee.target, damage);
+        let (entities, mut events, mut rng, mut wants_to_melee, names, combat_stats, mut inflict_damage, equipped, melee_power_bonuses, defense_bonuses, attributes, skills, attack_costs, mut energies, positions) = data;
+
+        // every swing rolls dice, so they're taken in an order that survives a reload
+        let mut attackers: Vec<Entity> = (&entities, &wants_to_melee).join().map(|(entity, _wants_melee)| entity).collect();
+        sort_by_position(&mut attackers, &positions);
+
+        for entity in attackers {
+            let wants_melee = wants_to_melee.get(entity).unwrap();
+            let (name, stats, attacker_attributes, attacker_skills) = match (names.get(entity), combat_stats.get(entity), attributes.get(entity), skills.get(entity)) {
+                (Some(name), Some(stats), Some(attributes), Some(skills)) => (name, stats, attributes, skills),
+                _ => continue
+            };
+            // the target may have been killed earlier this round, or never have been something to fight
+            let (target_name, target_stats, target_attributes, target_skills) = match (names.get(wants_melee.target), combat_stats.get(wants_melee.target),
+                attributes.get(wants_melee.target), skills.get(wants_melee.target)) {
+                (Some(name), Some(stats), Some(attributes), Some(skills)) => (name, stats, attributes, skills),
+                _ => continue
+            };
+            if stats.hp < 1 || target_stats.hp < 1 { continue; }
+
+            // a heavy weapon eats into the attacker's next turn
+            for (attack_cost, equipped_by) in (&attack_costs, &equipped).join() {
+                if equipped_by.owner == entity {
+                    if let Some(energy) = energies.get_mut(entity) {
+                        energy.energy -= attack_cost.cost - ACTION_COST;
                     }
                 }
             }
+
+            let mut offensive_bonus = 0;
+            for (power_bonus, equipped_by) in (&melee_power_bonuses, &equipped).join() {
+                if equipped_by.owner == entity {
+                    offensive_bonus += power_bonus.power;
+                }
+            }
+
+            let mut defensive_bonus = 0;
+            for (defense_bonus, equipped_by) in (&defense_bonuses, &equipped).join() {
+                if equipped_by.owner == wants_melee.target {
+                    defensive_bonus += defense_bonus.defense;
+                }
+            }
+
+            let natural_roll = rng.roll_dice(1, 20);
+            let attack_roll = natural_roll + gamesystem::attack_bonus(attacker_attributes, attacker_skills) + offensive_bonus;
+            let armor_class = BASE_ARMOR_CLASS + gamesystem::dodge(target_attributes, target_skills) + defensive_bonus;
+            let damage_dice = gamesystem::melee_damage(attacker_attributes, stats.damage);
+
+            let attacker_name = name.name.clone();
+            let target_name = target_name.name.clone();
+            if natural_roll <= FUMBLE_ROLL {
+                events.emit(GameEvent::AttackMissed { attacker: entity, attacker_name, target: wants_melee.target, target_name, fumble: true });
+            } else if natural_roll >= CRITICAL_ROLL {
+                let dice = DiceType { n_dice: damage_dice.n_dice * 2, ..damage_dice };
+                let damage = i32::max(1, rng.roll(dice) + offensive_bonus);
+                SufferDamage::new_damage(&mut inflict_damage, wants_melee.target, damage, Some(entity));
+                events.emit(GameEvent::DamageDealt { source: Some(entity), source_name: attacker_name, target: wants_melee.target, target_name, amount: damage, kind: DamageKind::Critical });
+            } else if attack_roll < armor_class {
+                events.emit(GameEvent::AttackMissed { attacker: entity, attacker_name, target: wants_melee.target, target_name, fumble: false });
+            } else {
+                let damage = i32::max(1, rng.roll(damage_dice) + offensive_bonus);
+                SufferDamage::new_damage(&mut inflict_damage, wants_melee.target, damage, Some(entity));
+                events.emit(GameEvent::DamageDealt { source: Some(entity), source_name: attacker_name, target: wants_melee.target, target_name, amount: damage, kind: DamageKind::Melee });
+            }
         }
         wants_to_melee.clear();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{State, raws};
+
+    /// An rng whose first d20 comes up `natural`.
+    fn rng_rolling(natural: i32) -> RandomNumberGenerator {
+        (0u64..).map(RandomNumberGenerator::seeded).find(|rng| rng.clone().roll_dice(1, 20) == natural).unwrap()
+    }
+
+    fn fighter(gs: &mut State, name: &str, skills: Skills) -> Entity {
+        gs.ecs.create_entity()
+            .with(Name { name: name.to_string() })
+            .with(CombatStats { max_hp: 10, hp: 10, damage: DiceType::new(1, 1, 0) })
+            .with(Attributes { might: 10, fitness: 10, quickness: 10, intelligence: 10 })
+            .with(skills)
+            .build()
+    }
+
+    /// Has `attacker` swing at `target` once with the given natural roll, and returns the events it made.
+    fn attack(gs: &mut State, attacker: Entity, target: Entity, natural: i32) -> Vec<GameEvent> {
+        gs.ecs.insert(rng_rolling(natural));
+        gs.ecs.write_resource::<EventQueue>().clear();
+        gs.ecs.write_storage::<WantsToMelee>().insert(attacker, WantsToMelee { target }).unwrap();
+        MeleeCombatSystem{}.run_now(&gs.ecs);
+        gs.ecs.fetch::<EventQueue>().iter().cloned().collect()
+    }
+
+    #[test]
+    fn a_fumble_always_misses() {
+        let mut gs = State::new_game(1, None, false, raws::load_raws().unwrap());
+        let attacker = fighter(&mut gs, "Attacker", Skills { melee: 100, ..Default::default() });
+        let target = fighter(&mut gs, "Target", Skills::default());
+
+        let events = attack(&mut gs, attacker, target, FUMBLE_ROLL);
+        assert!(matches!(events.as_slice(), [GameEvent::AttackMissed { fumble: true, .. }]), "{:?}", events);
+        assert!(gs.ecs.read_storage::<SufferDamage>().get(target).is_none());
+    }
+
+    #[test]
+    fn a_critical_always_hits_and_doubles_the_dice() {
+        let mut gs = State::new_game(1, None, false, raws::load_raws().unwrap());
+        let attacker = fighter(&mut gs, "Attacker", Skills::default());
+        let target = fighter(&mut gs, "Target", Skills { dodge: 100, ..Default::default() });
+
+        let events = attack(&mut gs, attacker, target, CRITICAL_ROLL);
+        assert!(matches!(events.as_slice(), [GameEvent::DamageDealt { amount: 2, kind: DamageKind::Critical, .. }]), "{:?}", events);
+        assert_eq!(gs.ecs.read_storage::<SufferDamage>().get(target).unwrap().amount, vec![(2, Some(attacker))]);
+    }
+
+    #[test]
+    fn an_ordinary_roll_has_to_beat_the_armor_class() {
+        let mut gs = State::new_game(1, None, false, raws::load_raws().unwrap());
+        let attacker = fighter(&mut gs, "Attacker", Skills::default());
+        let target = fighter(&mut gs, "Target", Skills { dodge: 100, ..Default::default() });
+
+        let events = attack(&mut gs, attacker, target, CRITICAL_ROLL - 1);
+        assert!(matches!(events.as_slice(), [GameEvent::AttackMissed { fumble: false, .. }]), "{:?}", events);
+    }
+
+    #[test]
+    fn swinging_at_something_that_cant_fight_does_nothing() {
+        let mut gs = State::new_game(1, None, false, raws::load_raws().unwrap());
+        let attacker = fighter(&mut gs, "Attacker", Skills::default());
+        let statue = gs.ecs.create_entity()
+            .with(Name { name: "Statue".to_string() })
+            .with(CombatStats { max_hp: 10, hp: 10, damage: DiceType::new(1, 1, 0) })
+            .build();
+        assert!(attack(&mut gs, attacker, statue, CRITICAL_ROLL).is_empty());
+
+        let gone = fighter(&mut gs, "Gone", Skills::default());
+        gs.ecs.delete_entity(gone).unwrap();
+        assert!(attack(&mut gs, attacker, gone, CRITICAL_ROLL).is_empty());
+    }
+}
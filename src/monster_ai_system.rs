@@ -1,7 +1,16 @@
-use super::{Map, Monster, Position, Viewshed, WantsToMelee, RunState};
-use rltk::{Point};
+use super::{Map, Monster, Position, Viewshed, WantsToMelee, MyTurn, Energy, ACTION_COST, StatusEffect, StatusEffectKind, Alerted, EntityMoved, Door, BlocksTile, BlocksVisibility, Renderable, InBackpack, Spell, Ranged, WantsToUse, Brain, AiMode, CombatStats, TileType, Faction, Ally, Name, sort_by_position, status_effect_system::has_status_effect, raws::{RawMaster, Reaction}};
+use rltk::{Point, RandomNumberGenerator};
 use specs::prelude::*;
 
+/// Monsters at or below this much of their health run from whatever they're fighting.
+const FLEE_HEALTH_PERCENT: i32 = 25;
+/// An idle monster sets off wandering on a 1 in this many roll each turn.
+const WANDER_CHANCE: i32 = 4;
+/// How far from home a wandering monster strays.
+const WANDER_RADIUS: i32 = 6;
+/// Allies further than this from the player go and catch up.
+const FOLLOW_DISTANCE: f32 = 2.0;
+
 pub struct MonsterAI {}
 
 impl<'a> System<'a> for MonsterAI {
@@ -9,46 +18,239 @@ impl<'a> System<'a> for MonsterAI {
     type SystemData = (
         WriteExpect<'a, Map>,
         ReadExpect<'a, Point>,
-        ReadExpect<'a, Entity>,
-        ReadExpect<'a, RunState>,
         Entities<'a>,
         WriteStorage<'a, Viewshed>,
         ReadStorage<'a, Monster>,
         WriteStorage<'a, Position>,
         WriteStorage<'a, WantsToMelee>,
+        ReadStorage<'a, StatusEffect>,
+        WriteExpect<'a, RandomNumberGenerator>,
+        WriteStorage<'a, Alerted>,
+        WriteStorage<'a, EntityMoved>,
+        WriteStorage<'a, Door>,
+        WriteStorage<'a, BlocksTile>,
+        WriteStorage<'a, BlocksVisibility>,
+        WriteStorage<'a, Renderable>,
+        (ReadStorage<'a, InBackpack>,
+         ReadStorage<'a, Spell>,
+         ReadStorage<'a, Ranged>,
+         ReadStorage<'a, Name>),
+        WriteStorage<'a, WantsToUse>,
+        WriteStorage<'a, Brain>,
+        ReadStorage<'a, CombatStats>,
+        ReadStorage<'a, Faction>,
+        ReadExpect<'a, RawMaster>,
+        ReadStorage<'a, Ally>,
+        WriteStorage<'a, MyTurn>,
+        WriteStorage<'a, Energy>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (mut map, player_pos, player_entity, runstate, entities, mut viewshed, monster, mut position, mut wants_to_melee) = data;
-
-        if *runstate != RunState::MonsterTurn {return;}
-
-        for (entity, mut viewshed, _monster, mut pos) in
-            (&entities, &mut viewshed, &monster, &mut position).join()
-        {
-            let distance = rltk::DistanceAlg::Pythagoras.distance2d(Point::new(pos.x, pos.y), *player_pos);
-            if distance < 1.5 {
-                //Attack
-                // console::log(&format!("{} shouts insults", name.name));
-                wants_to_melee.insert(entity, WantsToMelee{target: *player_entity}).expect("Unable to insert attack");
+        let (mut map, player_pos, entities, mut viewsheds, monster, mut positions, mut wants_to_melee, status_effects, mut rng, mut alerted, mut entity_moved, mut doors, mut blockers, mut blocks_visibility, mut renderables, (backpack, spells, ranged, names), mut wants_to_use, mut brains, combat_stats, factions, raws, allies, mut my_turn, mut energies) = data;
+
+        // only monsters the scheduler has handed a turn to get to act
+        let mut monsters: Vec<Entity> = (&entities, &monster, &brains, &my_turn).join().map(|(entity, _monster, _brain, _turn)| entity).collect();
+        sort_by_position(&mut monsters, &positions);
+        my_turn.clear();
+        for entity in monsters {
+            if let Some(energy) = energies.get_mut(entity) {
+                energy.energy -= ACTION_COST;
+            }
+            let stats = match combat_stats.get(entity) { Some(stats) => stats, None => continue };
+            let my_faction = factions.get(entity).map(|f| f.name.as_str()).unwrap_or("");
+
+            // the nearest thing in sight it wants to fight, and the nearest it wants to get away from
+            let mut hostile: Option<(Entity, Point, f32)> = None;
+            let mut threat: Option<(Point, f32)> = None;
+            {
+                let my_pos = positions.get(entity).unwrap();
+                let viewshed = viewsheds.get(entity).unwrap();
+                for (other, other_faction, other_pos, other_stats) in (&entities, &factions, &positions, &combat_stats).join() {
+                    let other_point = Point::new(other_pos.x, other_pos.y);
+                    if other == entity || other_stats.hp < 1 || !viewshed.visible_tiles.contains(&other_point) { continue; }
+                    let distance = rltk::DistanceAlg::Pythagoras.distance2d(Point::new(my_pos.x, my_pos.y), other_point);
+                    // ties go to whichever comes first in map order, not to whichever has the lower id
+                    let closer = |nearest: f32, at: Point| (distance, other_point.y, other_point.x) < (nearest, at.y, at.x);
+                    match raws.faction_reaction(my_faction, &other_faction.name) {
+                        Reaction::Attack => if !matches!(hostile, Some((_, at, nearest)) if !closer(nearest, at)) { hostile = Some((other, other_point, distance)); },
+                        Reaction::Flee => if !matches!(threat, Some((at, nearest)) if !closer(nearest, at)) { threat = Some((other_point, distance)); },
+                        Reaction::Ignore => {}
+                    }
+                }
+            }
+
+            // allies go back to the player once there's nothing left to do, monsters go home
+            let fallback = if allies.get(entity).is_some() { AiMode::Follow } else { AiMode::ReturnHome };
+
+            let pos = positions.get_mut(entity).unwrap();
+            let viewshed = viewsheds.get_mut(entity).unwrap();
+            let brain = brains.get_mut(entity).unwrap();
+
+            // confused monsters either lose their turn or stagger somewhere at random
+            if has_status_effect(&status_effects, entity, StatusEffectKind::Confusion) {
+                if rng.roll_dice(1, 2) == 1 {
+                    let new_x = pos.x + rng.range(-1, 2);
+                    let new_y = pos.y + rng.range(-1, 2);
+                    if new_x > 0 && new_x < map.width - 1 && new_y > 0 && new_y < map.height - 1 {
+                        let new_idx = map.xy_idx(new_x, new_y);
+                        if !map.blocked[new_idx] {
+                            move_monster(&mut map, pos, viewshed, new_x, new_y);
+                            entity_moved.insert(entity, EntityMoved{}).expect("Unable to insert marker");
+                        }
+                    }
+                }
+                continue;
             }
-            else if viewshed.visible_tiles.contains(&*player_pos) {
-                //path to player        
+
+            // pick the mode for this turn: anything heard or seen overrides what it was doing
+            if let Some(alert) = alerted.get(entity) {
+                brain.mode = AiMode::Chase{ x: alert.x, y: alert.y };
+            }
+            alerted.remove(entity);
+            let hurt = stats.hp * 100 <= stats.max_hp * FLEE_HEALTH_PERCENT;
+            let flee_from = threat.map(|(point, _)| point).or(if hurt { hostile.map(|(_, point, _)| point) } else { None });
+            if flee_from.is_some() {
+                brain.mode = AiMode::Flee;
+            } else if let Some((_, target, _)) = hostile {
+                brain.mode = AiMode::Chase{ x: target.x, y: target.y };
+            } else if brain.mode == AiMode::Flee {
+                brain.mode = fallback;
+            }
+
+            let mut destination: Option<Point> = None;
+            match brain.mode {
+                AiMode::Idle => {
+                    if rng.roll_dice(1, WANDER_CHANCE) == 1 {
+                        let x = brain.home_x + rng.range(-WANDER_RADIUS, WANDER_RADIUS + 1);
+                        let y = brain.home_y + rng.range(-WANDER_RADIUS, WANDER_RADIUS + 1);
+                        if x > 0 && x < map.width - 1 && y > 0 && y < map.height - 1 && map.tiles[map.xy_idx(x, y)] == TileType::Floor {
+                            brain.mode = AiMode::Wander{ x, y };
+                            destination = Some(Point::new(x, y));
+                        }
+                    }
+                }
+                AiMode::Wander{ x, y } => destination = Some(Point::new(x, y)),
+                AiMode::ReturnHome => destination = Some(Point::new(brain.home_x, brain.home_y)),
+                AiMode::Follow => {
+                    if rltk::DistanceAlg::Pythagoras.distance2d(Point::new(pos.x, pos.y), *player_pos) > FOLLOW_DISTANCE {
+                        destination = Some(*player_pos);
+                    }
+                }
+                AiMode::Flee => {
+                    let threat_at = flee_from.unwrap_or_else(|| Point::new(pos.x, pos.y));
+                    if let Some((new_x, new_y)) = retreat_from(&map, pos, threat_at) {
+                        move_monster(&mut map, pos, viewshed, new_x, new_y);
+                        entity_moved.insert(entity, EntityMoved{}).expect("Unable to insert marker");
+                    } else if let Some((target, _, distance)) = hostile {
+                        // cornered
+                        if distance < 1.5 {
+                            wants_to_melee.insert(entity, WantsToMelee{target}).expect("Unable to insert attack");
+                        }
+                    }
+                }
+                AiMode::Chase{ x, y } => {
+                    // casters try each spell that reaches the target, through the same pipeline as using an item
+                    let mut spell_reaches = false;
+                    if let Some((_, target_pos, distance)) = hostile {
+                        let mut cast: Option<Entity> = None;
+                        let mut known: Vec<(Entity, &Spell, &Ranged, &str)> = (&entities, &backpack, &spells, &ranged, &names).join()
+                            .filter(|(_spell_entity, pack, _spell, _range, _name)| pack.owner == entity)
+                            .map(|(spell_entity, _pack, spell, range, name)| (spell_entity, spell, range, name.name.as_str()))
+                            .collect();
+                        known.sort_by_key(|(_spell_entity, _spell, _range, name)| *name);
+                        for (spell_entity, spell, range, _name) in known {
+                            if distance > range.range as f32 { continue; }
+                            spell_reaches = true;
+                            if rng.roll_dice(1, 100) <= spell.chance {
+                                cast = Some(spell_entity);
+                                break;
+                            }
+                        }
+                        if let Some(spell_entity) = cast {
+                            wants_to_use.insert(entity, WantsToUse{ item: spell_entity, target: Some(target_pos) }).expect("Unable to insert intent");
+                            continue;
+                        }
+                    }
+
+                    if let Some((target, target_pos, _)) = hostile.filter(|(_, _, distance)| *distance < 1.5) {
+                        // casters would rather back off than trade blows
+                        let retreat = if spell_reaches { retreat_from(&map, pos, target_pos) } else { None };
+                        if let Some((new_x, new_y)) = retreat {
+                            move_monster(&mut map, pos, viewshed, new_x, new_y);
+                            entity_moved.insert(entity, EntityMoved{}).expect("Unable to insert marker");
+                        } else {
+                            wants_to_melee.insert(entity, WantsToMelee{target}).expect("Unable to insert attack");
+                        }
+                    } else if !spell_reaches {
+                        // close enough to cast means holding position for the next chance
+                        destination = Some(Point::new(x, y));
+                    }
+                }
+            }
+
+            if let Some(target) = destination {
                 let path = rltk::a_star_search(
                     map.xy_idx(pos.x, pos.y) as i32,
-                    map.xy_idx(player_pos.x, player_pos.y) as i32,
+                    map.xy_idx(target.x, target.y) as i32,
                     &mut *map,
                 );
-                if path.success && path.steps.len() > 1 {
-                    let mut idx = map.xy_idx(pos.x, pos.y);
-                    map.blocked[idx] = false;
-                    pos.x = path.steps[1] as i32 % map.width;
-                    pos.y = path.steps[1] as i32 / map.width;
-                    idx = map.xy_idx(pos.x, pos.y);
-                    map.blocked[idx] = true;
+                if path.success && path.steps.len() > 1 && map.closed_doors[path.steps[1]] {
+                    // a closed door in the way takes the turn to open
+                    let door_idx = path.steps[1];
+                    for door_entity in map.tile_content[door_idx].iter() {
+                        if let Some(door) = doors.get_mut(*door_entity) {
+                            door.open = true;
+                            blockers.remove(*door_entity);
+                            blocks_visibility.remove(*door_entity);
+                            if let Some(renderable) = renderables.get_mut(*door_entity) {
+                                renderable.glyph = rltk::to_cp437('/');
+                            }
+                        }
+                    }
+                    map.closed_doors[door_idx] = false;
+                    map.blocked[door_idx] = false;
                     viewshed.dirty = true;
+                } else if path.success && path.steps.len() > 1 {
+                    let new_x = path.steps[1] as i32 % map.width;
+                    let new_y = path.steps[1] as i32 / map.width;
+                    move_monster(&mut map, pos, viewshed, new_x, new_y);
+                    entity_moved.insert(entity, EntityMoved{}).expect("Unable to insert marker");
+                } else {
+                    // got there, or there's no way there: lost the trail, or done wandering
+                    brain.mode = match brain.mode {
+                        AiMode::Chase{..} => fallback,
+                        AiMode::Follow => AiMode::Follow,
+                        _ => AiMode::Idle,
+                    };
                 }
             }
         }
     }
 }
+
+fn move_monster(map: &mut Map, pos: &mut Position, viewshed: &mut Viewshed, new_x: i32, new_y: i32) {
+    let idx = map.xy_idx(pos.x, pos.y);
+    map.blocked[idx] = false;
+    pos.x = new_x;
+    pos.y = new_y;
+    let new_idx = map.xy_idx(pos.x, pos.y);
+    map.blocked[new_idx] = true;
+    viewshed.dirty = true;
+}
+
+/// The open neighbouring tile that gets furthest from `threat`, if any gets further than standing still.
+fn retreat_from(map: &Map, pos: &Position, threat: Point) -> Option<(i32, i32)> {
+    let mut best = rltk::DistanceAlg::Pythagoras.distance2d(Point::new(pos.x, pos.y), threat);
+    let mut retreat: Option<(i32, i32)> = None;
+    for (dx, dy) in [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)].iter() {
+        let (new_x, new_y) = (pos.x + dx, pos.y + dy);
+        if new_x < 1 || new_x > map.width - 2 || new_y < 1 || new_y > map.height - 2 { continue; }
+        if map.blocked[map.xy_idx(new_x, new_y)] { continue; }
+        let new_distance = rltk::DistanceAlg::Pythagoras.distance2d(Point::new(new_x, new_y), threat);
+        if new_distance > best {
+            best = new_distance;
+            retreat = Some((new_x, new_y));
+        }
+    }
+    retreat
+}
@@ -1,9 +1,22 @@
-use rltk::{VirtualKeyCode, Rltk};
+use rltk::{VirtualKeyCode, Rltk, RandomNumberGenerator};
 use specs::prelude::*;
-use super::{Position, Player, Viewshed, State, Map, RunState, Point, CombatStats, WantsToMelee, WantsToPickupItem, gamelog::GameLog, Item, spawner};
+use super::{Position, Player, Viewshed, State, Map, RunState, Point, CombatStats, WantsToMelee, WantsToPickupItem, gamelog::GameLog, Item, Action, Spawnable, Experience, LevelUpChoice, Attributes, InBackpack, EntityMoved, Door, BlocksTile, BlocksVisibility, Renderable, StatusEffect, StatusEffectKind, status_effect_system::has_status_effect, Faction, Ally, Name, gamesystem};
 use std::cmp::{min, max};
 
 pub fn try_move_player(delta_x: i32, delta_y: i32, ecs: &mut World) {
+    // confusion sends the player staggering off somewhere random half the time
+    let (delta_x, delta_y) = {
+        let player_entity = ecs.fetch::<Entity>();
+        let status_effects = ecs.read_storage::<StatusEffect>();
+        let mut rng = ecs.write_resource::<RandomNumberGenerator>();
+        if has_status_effect(&status_effects, *player_entity, StatusEffectKind::Confusion) && rng.roll_dice(1, 2) == 1 {
+            (rng.range(-1, 2), rng.range(-1, 2))
+        } else {
+            (delta_x, delta_y)
+        }
+    };
+    if delta_x == 0 && delta_y == 0 { return; }
+
     let mut positions = ecs.write_storage::<Position>();
     let mut players = ecs.write_storage::<Player>();
     let mut viewsheds = ecs.write_storage::<Viewshed>();
@@ -13,6 +26,16 @@ pub fn try_move_player(delta_x: i32, delta_y: i32, ecs: &mut World) {
 
     let entities = ecs.entities();
     let mut wants_to_melee = ecs.write_storage::<WantsToMelee>();
+    let mut entity_moved = ecs.write_storage::<EntityMoved>();
+    let mut doors = ecs.write_storage::<Door>();
+    let mut blockers = ecs.write_storage::<BlocksTile>();
+    let mut blocks_visibility = ecs.write_storage::<BlocksVisibility>();
+    let mut renderables = ecs.write_storage::<Renderable>();
+    let factions = ecs.read_storage::<Faction>();
+    let names = ecs.read_storage::<Name>();
+    let allies = ecs.read_storage::<Ally>();
+    let mut log = ecs.write_resource::<GameLog>();
+    let mut swapped: Option<(Entity, i32, i32)> = None;
 
     for (entity, _player, pos, viewshed) in (&entities, &mut players, &mut positions, &mut viewsheds).join() {
         let destination_idx = map.xy_idx(pos.x + delta_x, pos.y + delta_y);
@@ -20,11 +43,29 @@ pub fn try_move_player(delta_x: i32, delta_y: i32, ecs: &mut World) {
         for potential_target in map.tile_content[destination_idx].iter() {
             let target = combat_stats.get(*potential_target);
             if let Some(_target) = target {
-                wants_to_melee.insert(entity, WantsToMelee { target: *potential_target }).expect("Add target failed");
+                // companions and the player's own side get stepped past, anyone else bumped into is attacked
+                let friendly = allies.get(*potential_target).is_some()
+                    || matches!((factions.get(*potential_target), factions.get(entity)), (Some(theirs), Some(mine)) if theirs.name == mine.name);
+                if friendly {
+                    swapped = Some((*potential_target, pos.x, pos.y));
+                } else {
+                    wants_to_melee.insert(entity, WantsToMelee { target: *potential_target }).expect("Add target failed");
+                }
+            }
+            if let Some(door) = doors.get_mut(*potential_target) {
+                if !door.open {
+                    door.open = true;
+                    blockers.remove(*potential_target);
+                    blocks_visibility.remove(*potential_target);
+                    if let Some(renderable) = renderables.get_mut(*potential_target) {
+                        renderable.glyph = rltk::to_cp437('/');
+                    }
+                    viewshed.dirty = true;
+                }
             }
         }
         
-        if !map.blocked[destination_idx] {
+        if !map.blocked[destination_idx] || swapped.is_some() {
             pos.x = min(79 , max(0, pos.x + delta_x));
             pos.y = min(49, max(0, pos.y + delta_y));
 
@@ -33,61 +74,92 @@ pub fn try_move_player(delta_x: i32, delta_y: i32, ecs: &mut World) {
             ppos.y = pos.y;
 
             viewshed.dirty = true;
+            entity_moved.insert(entity, EntityMoved{}).expect("Unable to insert marker");
+        }
+    }
+
+    if let Some((other, x, y)) = swapped {
+        if let Some(other_pos) = positions.get_mut(other) {
+            other_pos.x = x;
+            other_pos.y = y;
+        }
+        if let Some(other_viewshed) = viewsheds.get_mut(other) {
+            other_viewshed.dirty = true;
+        }
+        entity_moved.insert(other, EntityMoved{}).expect("Unable to insert marker");
+        if let Some(name) = names.get(other) {
+            log.entries.push(format!("You swap places with the {}.", name.name));
         }
     }
 }
 
 pub fn player_input(gs: &mut State, ctx: &mut Rltk) -> RunState {
     let mouse_pos = ctx.mouse_pos();
-    match ctx.key {
+    let action = match ctx.key {
         None => {return RunState::AwaitingInput}
         Some(key) => match key {
             VirtualKeyCode::Left |
             VirtualKeyCode::A |
-            VirtualKeyCode::H => try_move_player(-1, 0, &mut gs.ecs),
+            VirtualKeyCode::H => Action::Move { delta_x: -1, delta_y: 0 },
 
             VirtualKeyCode::Right |
             VirtualKeyCode::D |
-            VirtualKeyCode::L => try_move_player(1, 0, &mut gs.ecs),
+            VirtualKeyCode::L => Action::Move { delta_x: 1, delta_y: 0 },
 
             VirtualKeyCode::Up |
             VirtualKeyCode::W |
-            VirtualKeyCode::K => try_move_player(0, -1, &mut gs.ecs),
+            VirtualKeyCode::K => Action::Move { delta_x: 0, delta_y: -1 },
 
             VirtualKeyCode::Down |
             VirtualKeyCode::S |
-            VirtualKeyCode::J => try_move_player(0, 1, &mut gs.ecs),
+            VirtualKeyCode::J => Action::Move { delta_x: 0, delta_y: 1 },
 
             VirtualKeyCode::E | 
-            VirtualKeyCode::O => try_move_player(1, -1, &mut gs.ecs),
+            VirtualKeyCode::O => Action::Move { delta_x: 1, delta_y: -1 },
 
             VirtualKeyCode::Q |
-            VirtualKeyCode::U => try_move_player(-1, -1, &mut gs.ecs),
+            VirtualKeyCode::U => Action::Move { delta_x: -1, delta_y: -1 },
 
             VirtualKeyCode::C |
-            VirtualKeyCode::M => try_move_player(1, 1, &mut gs.ecs),
+            VirtualKeyCode::M => Action::Move { delta_x: 1, delta_y: 1 },
 
             VirtualKeyCode::Z |
-            VirtualKeyCode::N => try_move_player(-1, 1, &mut gs.ecs),
+            VirtualKeyCode::N => Action::Move { delta_x: -1, delta_y: 1 },
 
-            VirtualKeyCode::G => get_item(&mut gs.ecs),
+            VirtualKeyCode::G => Action::PickUp,
+
+            VirtualKeyCode::Period => Action::Descend,
 
             VirtualKeyCode::I => return RunState::ShowInventory,
 
             VirtualKeyCode::F => return RunState::ShowDropItem,
 
+            VirtualKeyCode::P => return RunState::ShowCharacterSheet,
+
+            VirtualKeyCode::Escape => return RunState::SaveGame,
+
+            VirtualKeyCode::F3 => {
+                gs.show_ai_debug = !gs.show_ai_debug;
+                return RunState::AwaitingInput;
+            }
+
+            VirtualKeyCode::F4 => {
+                gs.show_system_timings = !gs.show_system_timings;
+                return RunState::AwaitingInput;
+            }
+
             //spawnables for testing
-            VirtualKeyCode::Key1 =>  spawner::fireball(&mut gs.ecs, mouse_pos.0, mouse_pos.1),
-            VirtualKeyCode::Key2 =>  spawner::magic_missile(&mut gs.ecs, mouse_pos.0, mouse_pos.1),
-            VirtualKeyCode::Key3 =>  spawner::health_potion(&mut gs.ecs, mouse_pos.0, mouse_pos.1),
+            VirtualKeyCode::Key1 => Action::Spawn { spawnable: Spawnable::Fireball, x: mouse_pos.0, y: mouse_pos.1 },
+            VirtualKeyCode::Key2 => Action::Spawn { spawnable: Spawnable::MagicMissile, x: mouse_pos.0, y: mouse_pos.1 },
+            VirtualKeyCode::Key3 => Action::Spawn { spawnable: Spawnable::HealthPotion, x: mouse_pos.0, y: mouse_pos.1 },
 
             _ => { return RunState::AwaitingInput}
         }
-    }
-    RunState::PlayerTurn
+    };
+    gs.perform(action)
 }
 
-fn get_item(ecs: &mut World) {
+pub fn get_item(ecs: &mut World) {
     let player_pos = ecs.fetch::<Point>();
     let player_entity = ecs.fetch::<Entity>();
     let entities = ecs.entities();
@@ -102,11 +174,111 @@ fn get_item(ecs: &mut World) {
         }
     }
 
+    let backpack = ecs.read_storage::<InBackpack>();
+    let carried = backpack.join().filter(|pack| pack.owner == *player_entity).count() as i32;
+    let capacity = ecs.read_storage::<Attributes>().get(*player_entity).map(gamesystem::carry_capacity).unwrap_or(0);
+
     match target_item {
         None => gamelog.entries.push("There is nothing here to pick up.".to_string()),
+        Some(_) if carried >= capacity => gamelog.entries.push("You can't carry any more.".to_string()),
         Some(item) => {
             let mut pickup = ecs.write_storage::<WantsToPickupItem>();
             pickup.insert(*player_entity, WantsToPickupItem{ collected_by: *player_entity, item }).expect("Unable to insert want to pickup");
         }
     }
-}
\ No newline at end of file
+}
+
+/// How far a level up raises the chosen attribute, enough to move its bonus by one.
+const LEVEL_UP_ATTRIBUTE_GAIN: i32 = 2;
+
+/// Spends one pending level on the attribute the player picked, and patches them up while at it.
+pub fn level_up(ecs: &mut World, choice: LevelUpChoice) {
+    let player_entity = ecs.fetch::<Entity>();
+    let mut experience = ecs.write_storage::<Experience>();
+    let mut attributes = ecs.write_storage::<Attributes>();
+    let mut combat_stats = ecs.write_storage::<CombatStats>();
+    let mut gamelog = ecs.fetch_mut::<GameLog>();
+
+    let exp = experience.get_mut(*player_entity).unwrap();
+    if !exp.level_up_pending() { return; }
+    exp.xp -= exp.xp_to_next_level();
+    exp.level += 1;
+
+    let attrs = attributes.get_mut(*player_entity).unwrap();
+    match choice {
+        LevelUpChoice::Might => attrs.might += LEVEL_UP_ATTRIBUTE_GAIN,
+        LevelUpChoice::Fitness => attrs.fitness += LEVEL_UP_ATTRIBUTE_GAIN,
+        LevelUpChoice::Quickness => attrs.quickness += LEVEL_UP_ATTRIBUTE_GAIN,
+        LevelUpChoice::Intelligence => attrs.intelligence += LEVEL_UP_ATTRIBUTE_GAIN,
+    }
+
+    let stats = combat_stats.get_mut(*player_entity).unwrap();
+    stats.max_hp = gamesystem::max_hp(attrs, exp.level);
+    stats.hp = stats.max_hp;
+    gamelog.entries.push(format!("Welcome to level {}!", exp.level));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MapIndexingSystem, TileType, raws};
+
+    #[test]
+    fn levelling_up_spends_the_xp_and_raises_the_choice() {
+        let mut gs = State::new_game(1, None, false, raws::load_raws().unwrap());
+        let player = *gs.ecs.fetch::<Entity>();
+        let fitness = gs.ecs.read_storage::<Attributes>().get(player).unwrap().fitness;
+        {
+            let mut experience = gs.ecs.write_storage::<Experience>();
+            let exp = experience.get_mut(player).unwrap();
+            exp.xp = exp.xp_to_next_level() - 1;
+            assert!(!exp.level_up_pending());
+        }
+        gs.ecs.write_storage::<CombatStats>().get_mut(player).unwrap().hp = 1;
+
+        level_up(&mut gs.ecs, LevelUpChoice::Fitness);
+        assert_eq!(gs.ecs.read_storage::<Experience>().get(player).unwrap().level, 1, "not enough xp yet");
+
+        gs.ecs.write_storage::<Experience>().get_mut(player).unwrap().xp = 130;
+        assert!(gs.level_up_pending());
+        level_up(&mut gs.ecs, LevelUpChoice::Fitness);
+
+        let experience = gs.ecs.read_storage::<Experience>();
+        let exp = experience.get(player).unwrap();
+        assert_eq!((exp.level, exp.xp), (2, 30));
+        let attrs = gs.ecs.read_storage::<Attributes>().get(player).unwrap().clone();
+        assert_eq!(attrs.fitness, fitness + LEVEL_UP_ATTRIBUTE_GAIN);
+        let stats = gs.ecs.read_storage::<CombatStats>().get(player).unwrap().clone();
+        assert_eq!(stats.max_hp, gamesystem::max_hp(&attrs, 2));
+        assert_eq!(stats.hp, stats.max_hp);
+    }
+
+    #[test]
+    fn a_closed_door_takes_a_bump_to_open() {
+        let mut gs = State::new_game(1, None, false, raws::load_raws().unwrap());
+        let player = *gs.ecs.fetch::<Entity>();
+        let start = *gs.ecs.fetch::<Point>();
+        let door = raws::spawn_named_entity(&mut gs.ecs, "Door", start.x + 1, start.y).unwrap();
+        {
+            let mut map = gs.ecs.fetch_mut::<Map>();
+            let idx = map.xy_idx(start.x + 1, start.y);
+            map.tiles[idx] = TileType::Floor;
+        }
+        MapIndexingSystem{}.run_now(&gs.ecs);
+        let door_idx = gs.ecs.fetch::<Map>().xy_idx(start.x + 1, start.y);
+        assert!(gs.ecs.fetch::<Map>().blocked[door_idx]);
+        assert!(gs.ecs.fetch::<Map>().closed_doors[door_idx]);
+
+        try_move_player(1, 0, &mut gs.ecs);
+        assert_eq!(*gs.ecs.fetch::<Point>(), start, "opening the door is the move");
+        assert!(gs.ecs.read_storage::<Door>().get(door).unwrap().open);
+        assert!(gs.ecs.read_storage::<BlocksTile>().get(door).is_none());
+        assert!(gs.ecs.read_storage::<BlocksVisibility>().get(door).is_none());
+
+        MapIndexingSystem{}.run_now(&gs.ecs);
+        assert!(!gs.ecs.fetch::<Map>().closed_doors[door_idx]);
+        try_move_player(1, 0, &mut gs.ecs);
+        let pos = gs.ecs.read_storage::<Position>().get(player).unwrap().clone();
+        assert_eq!((pos.x, pos.y), (start.x + 1, start.y));
+    }
+}
@@ -0,0 +1,12 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// How one faction feels about the others, keyed by faction name with `Default` covering the rest.
+#[derive(Deserialize, Debug)]
+pub struct FactionInfo {
+    pub name: String,
+    pub responses: HashMap<String, Reaction>,
+}
+
+#[derive(Deserialize, PartialEq, Copy, Clone, Debug)]
+pub enum Reaction { Attack, Ignore, Flee }
@@ -0,0 +1,48 @@
+use serde::Deserialize;
+use crate::components::{EquipmentSlot, StatusEffectKind};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Item {
+    pub name: String,
+    pub renderable: Option<Renderable>,
+    #[serde(default)]
+    pub consumable: bool,
+    #[serde(default)]
+    pub effects: ItemEffects,
+    pub equippable: Option<Equippable>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Equippable {
+    pub slot: EquipmentSlot,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Renderable {
+    pub glyph: String,
+    pub fg: String,
+    pub bg: String,
+    pub order: i32,
+}
+
+/// Each field maps onto the component of the same name, anything left out isn't added.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ItemEffects {
+    pub provides_healing: Option<i32>,
+    pub inflict_damage: Option<i32>,
+    pub ranged: Option<i32>,
+    pub area_of_effect: Option<i32>,
+    pub melee_power_bonus: Option<i32>,
+    /// Energy an attack with this weapon takes, heavier weapons are slower to swing.
+    pub attack_cost: Option<i32>,
+    pub defense_bonus: Option<i32>,
+    #[serde(default)]
+    pub provides_food: bool,
+    pub status: Option<StatusEffect>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct StatusEffect {
+    pub effect: StatusEffectKind,
+    pub turns: i32,
+}
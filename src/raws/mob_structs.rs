@@ -0,0 +1,36 @@
+use serde::Deserialize;
+use super::Renderable;
+use crate::components::{Attributes, Skills};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Mob {
+    pub name: String,
+    pub renderable: Option<Renderable>,
+    #[serde(default)]
+    pub blocks_tile: bool,
+    pub faction: String,
+    /// Joins the player's party as soon as it's spawned.
+    #[serde(default)]
+    pub ally: bool,
+    pub attributes: Attributes,
+    #[serde(default)]
+    pub skills: Skills,
+    pub damage: String,
+    pub vision_range: i32,
+    /// Energy gained each turn; 100 acts as often as the player.
+    #[serde(default = "default_speed")]
+    pub speed: i32,
+    #[serde(default)]
+    pub xp: i32,
+    #[serde(default)]
+    pub spells: Vec<MobSpell>,
+}
+
+fn default_speed() -> i32 { 100 }
+
+/// An item from the raws the mob can use at range, and the percent chance it tries each turn.
+#[derive(Deserialize, Debug, Clone)]
+pub struct MobSpell {
+    pub name: String,
+    pub chance: i32,
+}
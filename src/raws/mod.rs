@@ -0,0 +1,36 @@
+use serde::Deserialize;
+mod item_structs;
+pub use item_structs::*;
+mod mob_structs;
+pub use mob_structs::*;
+mod prop_structs;
+pub use prop_structs::*;
+mod faction_structs;
+pub use faction_structs::*;
+mod spawn_table_structs;
+pub use spawn_table_structs::*;
+mod rawmaster;
+pub use rawmaster::*;
+
+/// Shipped inside the binary so the game still has content where there's no file system (wasm).
+const EMBEDDED_RAWS: &str = include_str!("../../raws/spawns.json");
+const RAWS_PATH: &str = "./raws/spawns.json";
+
+#[derive(Deserialize, Debug)]
+pub struct Raws {
+    pub items: Vec<Item>,
+    pub mobs: Vec<Mob>,
+    #[serde(default)]
+    pub props: Vec<Prop>,
+    pub spawn_table: Vec<SpawnTableEntry>,
+    #[serde(default)]
+    pub faction_table: Vec<FactionInfo>,
+}
+
+/// Reads the entity definitions, preferring the file next to the game so they can be edited without a rebuild.
+/// This is done once at startup and the result handed to every new game.
+pub fn load_raws() -> Result<RawMaster, String> {
+    let data = std::fs::read_to_string(RAWS_PATH).unwrap_or_else(|_| EMBEDDED_RAWS.to_string());
+    let raws: Raws = serde_json::from_str(&data).map_err(|e| format!("Unable to parse {}: {}", RAWS_PATH, e))?;
+    RawMaster::load(raws).map_err(|e| format!("Invalid entries in {}:\n{}", RAWS_PATH, e))
+}
@@ -0,0 +1,32 @@
+use serde::Deserialize;
+use super::Renderable;
+
+/// Things that sit in the dungeon without being picked up or fought, like traps and doors.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Prop {
+    pub name: String,
+    pub renderable: Option<Renderable>,
+    #[serde(default)]
+    pub hidden: bool,
+    #[serde(default)]
+    pub entry_trigger: bool,
+    #[serde(default)]
+    pub blocks_tile: bool,
+    #[serde(default)]
+    pub blocks_visibility: bool,
+    #[serde(default)]
+    pub door: bool,
+    #[serde(default)]
+    pub effects: PropEffects,
+}
+
+/// What happens to whatever sets the prop off.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct PropEffects {
+    pub inflict_damage: Option<i32>,
+    #[serde(default)]
+    pub teleport: bool,
+    pub alarm: Option<i32>,
+    #[serde(default)]
+    pub single_activation: bool,
+}
@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+use specs::prelude::*;
+use rltk::RGB;
+use super::{Raws, Renderable, Reaction};
+use crate::components::*;
+use crate::gamesystem;
+use crate::spawn_table::SpawnTable;
+
+/// The loaded raws plus a name index into them, kept as a world resource.
+pub struct RawMaster {
+    raws: Raws,
+    item_index: HashMap<String, usize>,
+    mob_index: HashMap<String, usize>,
+    prop_index: HashMap<String, usize>,
+    spawn_table: SpawnTable,
+    faction_index: HashMap<String, HashMap<String, Reaction>>,
+}
+
+enum Template {
+    Item(super::Item),
+    Mob(super::Mob),
+    Prop(super::Prop),
+}
+
+impl RawMaster {
+    /// Indexes the raws and checks everything a spawn would need to convert, so a typo in the
+    /// file is reported up front instead of turning up the first time that thing is spawned.
+    pub fn load(raws: Raws) -> Result<RawMaster, String> {
+        let mut item_index = HashMap::new();
+        for (i, item) in raws.items.iter().enumerate() {
+            if item_index.insert(item.name.clone(), i).is_some() {
+                rltk::console::log(format!("WARNING - duplicate item name in raws [{}]", item.name));
+            }
+        }
+        let mut mob_index = HashMap::new();
+        for (i, mob) in raws.mobs.iter().enumerate() {
+            if mob_index.insert(mob.name.clone(), i).is_some() {
+                rltk::console::log(format!("WARNING - duplicate mob name in raws [{}]", mob.name));
+            }
+        }
+        let mut prop_index = HashMap::new();
+        for (i, prop) in raws.props.iter().enumerate() {
+            if prop_index.insert(prop.name.clone(), i).is_some() {
+                rltk::console::log(format!("WARNING - duplicate prop name in raws [{}]", prop.name));
+            }
+        }
+
+        let mut spawn_table = SpawnTable::new();
+        for entry in raws.spawn_table.iter() {
+            if !item_index.contains_key(&entry.name) && !mob_index.contains_key(&entry.name) && !prop_index.contains_key(&entry.name) {
+                rltk::console::log(format!("WARNING - spawn table references unknown entity [{}]", entry.name));
+                continue;
+            }
+            spawn_table = spawn_table.add(&entry.name, entry.weight, entry.min_depth, entry.max_depth);
+        }
+
+        let mut faction_index = HashMap::new();
+        for faction in raws.faction_table.iter() {
+            faction_index.insert(faction.name.clone(), faction.responses.clone());
+        }
+
+        let mut errors: Vec<String> = Vec::new();
+        let renderables = raws.items.iter().map(|i| (&i.name, &i.renderable))
+            .chain(raws.mobs.iter().map(|m| (&m.name, &m.renderable)))
+            .chain(raws.props.iter().map(|p| (&p.name, &p.renderable)));
+        for (name, renderable) in renderables {
+            if let Some(Err(e)) = renderable.as_ref().map(get_renderable_component) {
+                errors.push(format!("{}: {}", name, e));
+            }
+        }
+        for mob in raws.mobs.iter() {
+            if rltk::parse_dice_string(&mob.damage).is_err() {
+                errors.push(format!("{}: invalid damage dice [{}]", mob.name, mob.damage));
+            }
+            if mob.speed <= 0 {
+                errors.push(format!("{}: speed must be above zero, got {}", mob.name, mob.speed));
+            }
+            for spell in mob.spells.iter() {
+                if !item_index.contains_key(&spell.name) {
+                    errors.push(format!("{}: knows unknown spell [{}]", mob.name, spell.name));
+                }
+            }
+            if !faction_index.contains_key(&mob.faction) {
+                errors.push(format!("{}: belongs to unknown faction [{}]", mob.name, mob.faction));
+            }
+        }
+        for faction in raws.faction_table.iter() {
+            let mut others: Vec<&String> = faction.responses.keys()
+                .filter(|other| other.as_str() != "Default" && !faction_index.contains_key(*other))
+                .collect();
+            others.sort();
+            for other in others {
+                errors.push(format!("{}: responds to unknown faction [{}]", faction.name, other));
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors.join("\n"));
+        }
+
+        Ok(RawMaster { raws, item_index, mob_index, prop_index, spawn_table, faction_index })
+    }
+
+    pub fn spawn_table(&self) -> &SpawnTable {
+        &self.spawn_table
+    }
+
+    /// How members of `my_faction` react to meeting `their_faction`; factions the table doesn't mention are ignored.
+    pub fn faction_reaction(&self, my_faction: &str, their_faction: &str) -> Reaction {
+        match self.faction_index.get(my_faction) {
+            None => Reaction::Ignore,
+            Some(responses) => responses.get(their_faction)
+                .or_else(|| responses.get("Default"))
+                .copied()
+                .unwrap_or(Reaction::Ignore),
+        }
+    }
+
+    fn template(&self, key: &str) -> Option<Template> {
+        if let Some(idx) = self.item_index.get(key) {
+            return Some(Template::Item(self.raws.items[*idx].clone()));
+        }
+        if let Some(idx) = self.mob_index.get(key) {
+            return Some(Template::Mob(self.raws.mobs[*idx].clone()));
+        }
+        if let Some(idx) = self.prop_index.get(key) {
+            return Some(Template::Prop(self.raws.props[*idx].clone()));
+        }
+        None
+    }
+}
+
+fn get_renderable_component(renderable: &Renderable) -> Result<crate::components::Renderable, String> {
+    let mut glyph = renderable.glyph.chars();
+    let glyph = match (glyph.next(), glyph.next()) {
+        (Some(glyph), None) => glyph,
+        _ => return Err(format!("glyph must be a single character, got [{}]", renderable.glyph)),
+    };
+    Ok(crate::components::Renderable {
+        glyph: rltk::to_cp437(glyph),
+        fg: RGB::from_hex(&renderable.fg).map_err(|_| format!("invalid colour [{}]", renderable.fg))?,
+        bg: RGB::from_hex(&renderable.bg).map_err(|_| format!("invalid colour [{}]", renderable.bg))?,
+        render_order: renderable.order
+    })
+}
+
+/// Creates whatever the raws call `key` at `(x, y)`. Nothing is created if the name is unknown
+/// or its definition doesn't convert, and the error says why.
+pub fn spawn_named_entity(ecs: &mut World, key: &str, x: i32, y: i32) -> Result<Entity, String> {
+    let template = ecs.fetch::<RawMaster>().template(key).ok_or_else(|| format!("nothing in the raws is called [{}]", key))?;
+
+    // convert anything that can fail before the entity exists, so an error never leaves half of one behind
+    let renderable = match &template {
+        Template::Item(item) => item.renderable.as_ref(),
+        Template::Mob(mob) => mob.renderable.as_ref(),
+        Template::Prop(prop) => prop.renderable.as_ref(),
+    }.map(get_renderable_component).transpose().map_err(|e| format!("{}: {}", key, e))?;
+    let damage = match &template {
+        Template::Mob(mob) => Some(rltk::parse_dice_string(&mob.damage).map_err(|_| format!("{}: invalid damage dice [{}]", key, mob.damage))?),
+        _ => None
+    };
+
+    let mut spells: Vec<super::MobSpell> = Vec::new();
+    let mut eb = ecs.create_entity().with(Position { x, y });
+    if let Some(renderable) = renderable {
+        eb = eb.with(renderable);
+    }
+    match template {
+        Template::Item(item) => {
+            eb = eb.with(Name { name: item.name.clone() });
+            eb = eb.with(Item {});
+            if item.consumable {
+                eb = eb.with(Consumable {});
+            }
+
+            let effects = &item.effects;
+            if let Some(heal_amount) = effects.provides_healing {
+                eb = eb.with(ProvidesHealing { heal_amount });
+            }
+            if let Some(damage) = effects.inflict_damage {
+                eb = eb.with(InflictDamage { damage });
+            }
+            if let Some(range) = effects.ranged {
+                eb = eb.with(Ranged { range });
+            }
+            if let Some(radius) = effects.area_of_effect {
+                eb = eb.with(AreaOfEffect { radius });
+            }
+            if let Some(power) = effects.melee_power_bonus {
+                eb = eb.with(MeleePowerBonus { power });
+            }
+            if let Some(cost) = effects.attack_cost {
+                eb = eb.with(AttackCost { cost });
+            }
+            if let Some(defense) = effects.defense_bonus {
+                eb = eb.with(DefenseBonus { defense });
+            }
+            if effects.provides_food {
+                eb = eb.with(ProvidesFood {});
+            }
+            if let Some(status) = &effects.status {
+                eb = eb.with(InflictsStatus { kind: status.effect, turns: status.turns });
+            }
+            if let Some(equippable) = &item.equippable {
+                eb = eb.with(Equippable { slot: equippable.slot });
+            }
+        }
+        Template::Mob(mob) => {
+            eb = eb.with(Name { name: mob.name.clone() });
+            eb = eb.with(Monster {});
+            if mob.ally {
+                eb = eb.with(Ally {});
+                eb = eb.with(Brain { mode: AiMode::Follow, home_x: x, home_y: y });
+            } else {
+                eb = eb.with(Brain { mode: AiMode::Idle, home_x: x, home_y: y });
+            }
+            eb = eb.with(Faction { name: mob.faction.clone() });
+            if mob.blocks_tile {
+                eb = eb.with(BlocksTile {});
+            }
+            let max_hp = gamesystem::max_hp(&mob.attributes, 1);
+            eb = eb.with(CombatStats {
+                max_hp,
+                hp: max_hp,
+                damage: damage.unwrap_or_default()
+            });
+            eb = eb.with(mob.attributes.clone());
+            eb = eb.with(mob.skills.clone());
+            eb = eb.with(Viewshed { visible_tiles: Vec::new(), range: mob.vision_range, dirty: true });
+            eb = eb.with(Energy { speed: mob.speed, energy: 0 });
+            if mob.xp > 0 {
+                eb = eb.with(GivesExperience { xp: mob.xp });
+            }
+            spells = mob.spells.clone();
+        }
+        Template::Prop(prop) => {
+            eb = eb.with(Name { name: prop.name.clone() });
+            if prop.hidden {
+                eb = eb.with(Hidden {});
+            }
+            if prop.entry_trigger {
+                eb = eb.with(EntryTrigger {});
+            }
+            if prop.blocks_tile {
+                eb = eb.with(BlocksTile {});
+            }
+            if prop.blocks_visibility {
+                eb = eb.with(BlocksVisibility {});
+            }
+            if prop.door {
+                eb = eb.with(Door { open: false });
+            }
+
+            let effects = &prop.effects;
+            if let Some(damage) = effects.inflict_damage {
+                eb = eb.with(InflictDamage { damage });
+            }
+            if effects.teleport {
+                eb = eb.with(TeleportsVictim {});
+            }
+            if let Some(radius) = effects.alarm {
+                eb = eb.with(Alarm { radius });
+            }
+            if effects.single_activation {
+                eb = eb.with(SingleActivation {});
+            }
+        }
+    }
+
+    let entity = eb.build();
+
+    // a mob's spells are items tucked away in its backpack
+    for spell in spells.iter() {
+        match spawn_named_entity(ecs, &spell.name, x, y) {
+            Err(e) => rltk::console::log(format!("WARNING - {} can't learn a spell: {}", key, e)),
+            Ok(spell_entity) => {
+                ecs.write_storage::<Position>().remove(spell_entity);
+                ecs.write_storage::<InBackpack>().insert(spell_entity, InBackpack { owner: entity }).expect("Unable to insert backpack entry");
+                ecs.write_storage::<Spell>().insert(spell_entity, Spell { chance: spell.chance }).expect("Unable to insert spell");
+            }
+        }
+    }
+
+    Ok(entity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raws::EMBEDDED_RAWS;
+
+    fn shipped_raws() -> Raws {
+        serde_json::from_str(EMBEDDED_RAWS).unwrap()
+    }
+
+    #[test]
+    fn the_shipped_raws_load() {
+        assert!(RawMaster::load(shipped_raws()).is_ok());
+    }
+
+    #[test]
+    fn unknown_factions_are_rejected() {
+        let mut raws = shipped_raws();
+        raws.mobs[0].faction = "Orks".to_string();
+        raws.faction_table[0].responses.insert("Gobbos".to_string(), Reaction::Attack);
+        let mob = raws.mobs[0].name.clone();
+        let faction = raws.faction_table[0].name.clone();
+
+        let errors = match RawMaster::load(raws) {
+            Ok(_) => panic!("a made up faction got through"),
+            Err(e) => e,
+        };
+        assert!(errors.contains(&format!("{}: belongs to unknown faction [Orks]", mob)), "{}", errors);
+        assert!(errors.contains(&format!("{}: responds to unknown faction [Gobbos]", faction)), "{}", errors);
+    }
+
+    #[test]
+    fn factions_react_by_the_table() {
+        let raws = RawMaster::load(shipped_raws()).unwrap();
+        assert_eq!(raws.faction_reaction("Orcs", "Player"), Reaction::Attack);
+        assert_eq!(raws.faction_reaction("Orcs", "Orcs"), Reaction::Ignore);
+        assert_eq!(raws.faction_reaction("Orcs", "Goblins"), Reaction::Attack, "falls back to Default");
+        assert_eq!(raws.faction_reaction("Beetles", "Player"), Reaction::Ignore);
+        assert_eq!(raws.faction_reaction("Nobody", "Player"), Reaction::Ignore);
+    }
+}
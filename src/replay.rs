@@ -0,0 +1,332 @@
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use rltk::{Point, RandomNumberGenerator};
+use serde::{Serialize, Deserialize};
+use specs::prelude::*;
+use super::{Action, Spawnable, LevelUpChoice, CombatStats, Position, Name, InBackpack, Equipped, GameSeed, Simulation, RunState, gamelog::GameLog, raws::RawMaster, map_builders::{BuilderKind, BuilderChoice}};
+
+/// Bump this whenever the shape of a recorded action changes.
+pub const REPLAY_VERSION: u32 = 5;
+
+/// Names one of the player's items in a way that survives a save and reload, which renumbers
+/// every entity. Items built from the same raw are interchangeable, so the name is enough to
+/// find one again, along with whether it was being worn or carried.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct ItemKey {
+    pub name: String,
+    pub equipped: bool,
+}
+
+impl ItemKey {
+    fn of(ecs: &World, item: Entity) -> ItemKey {
+        let name = ecs.read_storage::<Name>().get(item).map(|n| n.name.clone()).unwrap_or_default();
+        let equipped = ecs.read_storage::<Equipped>().get(item).is_some();
+        ItemKey { name, equipped }
+    }
+
+    /// Finds a matching item on the player, or says which one is missing.
+    fn resolve(&self, ecs: &World) -> Result<Entity, String> {
+        let player_entity = *ecs.fetch::<Entity>();
+        let entities = ecs.entities();
+        let names = ecs.read_storage::<Name>();
+        let backpack = ecs.read_storage::<InBackpack>();
+        let equipped = ecs.read_storage::<Equipped>();
+
+        let found = if self.equipped {
+            (&entities, &equipped, &names).join()
+                .find(|(_e, worn, name)| worn.owner == player_entity && name.name == self.name)
+                .map(|(e, _worn, _name)| e)
+        } else {
+            (&entities, &backpack, &names).join()
+                .find(|(_e, pack, name)| pack.owner == player_entity && name.name == self.name)
+                .map(|(e, _pack, _name)| e)
+        };
+        found.ok_or_else(|| format!("the player has no {} {}", if self.equipped { "equipped" } else { "carried" }, self.name))
+    }
+}
+
+/// An `Action` with its entities swapped for item keys, so it can be written out and resolved again on replay.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub enum RecordedCommand {
+    Move { delta_x: i32, delta_y: i32 },
+    PickUp,
+    UseItem { item: ItemKey, target: Option<Point> },
+    DropItem { item: ItemKey },
+    Spawn { spawnable: Spawnable, x: i32, y: i32 },
+    Descend,
+    LevelUp { choice: LevelUpChoice },
+}
+
+impl RecordedCommand {
+    fn from_action(ecs: &World, action: Action) -> RecordedCommand {
+        match action {
+            Action::Move { delta_x, delta_y } => RecordedCommand::Move { delta_x, delta_y },
+            Action::PickUp => RecordedCommand::PickUp,
+            Action::UseItem { item, target } => RecordedCommand::UseItem { item: ItemKey::of(ecs, item), target },
+            Action::DropItem { item } => RecordedCommand::DropItem { item: ItemKey::of(ecs, item) },
+            Action::Spawn { spawnable, x, y } => RecordedCommand::Spawn { spawnable, x, y },
+            Action::Descend => RecordedCommand::Descend,
+            Action::LevelUp { choice } => RecordedCommand::LevelUp { choice },
+        }
+    }
+
+    fn to_action(&self, ecs: &World) -> Result<Action, String> {
+        match self {
+            RecordedCommand::Move { delta_x, delta_y } => Ok(Action::Move { delta_x: *delta_x, delta_y: *delta_y }),
+            RecordedCommand::PickUp => Ok(Action::PickUp),
+            RecordedCommand::UseItem { item, target } => item.resolve(ecs).map(|item| Action::UseItem { item, target: *target }),
+            RecordedCommand::DropItem { item } => item.resolve(ecs).map(|item| Action::DropItem { item }),
+            RecordedCommand::Spawn { spawnable, x, y } => Ok(Action::Spawn { spawnable: *spawnable, x: *x, y: *y }),
+            RecordedCommand::Descend => Ok(Action::Descend),
+            RecordedCommand::LevelUp { choice } => Ok(Action::LevelUp { choice: *choice }),
+        }
+    }
+}
+
+/// A cheap fingerprint of the world, taken just before each action, that a replay has to reproduce.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct TurnDigest {
+    pub player_x: i32,
+    pub player_y: i32,
+    pub player_hp: i32,
+    pub entity_count: usize,
+    pub next_roll: u64,
+}
+
+impl TurnDigest {
+    pub fn capture(ecs: &World) -> TurnDigest {
+        let player_entity = ecs.fetch::<Entity>();
+        let positions = ecs.read_storage::<Position>();
+        let stats = ecs.read_storage::<CombatStats>();
+        let (player_x, player_y) = positions.get(*player_entity).map(|p| (p.x, p.y)).unwrap_or((-1, -1));
+        let player_hp = stats.get(*player_entity).map(|s| s.hp).unwrap_or(0);
+        let entity_count = ecs.entities().join().count();
+        // peek at the rng without advancing the real one
+        let next_roll = (*ecs.fetch::<RandomNumberGenerator>()).clone().next_u64();
+
+        TurnDigest { player_x, player_y, player_hp, entity_count, next_roll }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct RecordedAction {
+    pub turn: u32,
+    pub command: RecordedCommand,
+    pub before: TurnDigest,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ReplayHeader {
+    version: u32,
+    seed: u64,
+    builder: Option<BuilderKind>,
+}
+
+/// Every action taken this run, optionally mirrored line by line into a file as it happens.
+#[derive(Default)]
+pub struct ReplayRecorder {
+    pub actions: Vec<RecordedAction>,
+    /// Where recordings go, each run gets its own file in here named after its seed.
+    pub dir: Option<String>,
+    pub path: Option<String>,
+}
+
+/// Actions still to be fed back in while watching a replay.
+pub struct ReplayPlayback {
+    pub actions: Vec<RecordedAction>,
+    pub next: usize,
+}
+
+pub struct Replay {
+    pub seed: u64,
+    pub builder: Option<BuilderKind>,
+    pub actions: Vec<RecordedAction>,
+}
+
+/// What a replay found instead of what it expected.
+#[derive(Debug)]
+pub enum Mismatch {
+    /// The world no longer matches the recorded fingerprint.
+    Digest(TurnDigest),
+    /// The recorded action names an item the player doesn't have.
+    MissingItem(String),
+}
+
+#[derive(Debug)]
+pub struct Divergence {
+    pub turn: u32,
+    pub expected: TurnDigest,
+    pub found: Mismatch,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.found {
+            Mismatch::Digest(found) => write!(f, "replay diverged at turn {}: expected {:?}, found {:?}", self.turn, self.expected, found),
+            Mismatch::MissingItem(reason) => write!(f, "replay diverged at turn {}: {}", self.turn, reason),
+        }
+    }
+}
+
+/// The file a run with `seed` is recorded to inside `dir`.
+pub fn replay_path(dir: &str, seed: u64) -> String {
+    format!("{}/replay-{}.jsonl", dir, seed)
+}
+
+/// Starts mirroring the recorder into a file for the current seed inside `dir`, writing out
+/// anything already recorded. Other runs' recordings in there are left alone.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn start_recording(ecs: &mut World, dir: &str) {
+    let seed = ecs.fetch::<GameSeed>().seed;
+    let builder = ecs.fetch::<BuilderChoice>().kind;
+    let mut recorder = ecs.write_resource::<ReplayRecorder>();
+    let path = replay_path(dir, seed);
+    fs::create_dir_all(dir).expect("Unable to create replay directory");
+    let mut file = File::create(&path).expect("Unable to create replay file");
+    writeln!(file, "{}", serde_json::to_string(&ReplayHeader { version: REPLAY_VERSION, seed, builder }).unwrap()).expect("Unable to write replay");
+    for action in recorder.actions.iter() {
+        writeln!(file, "{}", serde_json::to_string(action).unwrap()).expect("Unable to write replay");
+    }
+    recorder.dir = Some(dir.to_string());
+    recorder.path = Some(path);
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn start_recording(_ecs: &mut World, _dir: &str) {}
+
+/// Notes down an action about to be performed.
+pub fn record(ecs: &mut World, action: Action) {
+    let before = TurnDigest::capture(ecs);
+    let command = RecordedCommand::from_action(ecs, action);
+    let mut recorder = ecs.write_resource::<ReplayRecorder>();
+    let recorded = RecordedAction { turn: recorder.actions.len() as u32, command, before };
+
+    if let Some(path) = &recorder.path {
+        let mut file = OpenOptions::new().append(true).open(path).expect("Unable to open replay file");
+        writeln!(file, "{}", serde_json::to_string(&recorded).unwrap()).expect("Unable to write replay");
+    }
+    recorder.actions.push(recorded);
+}
+
+pub fn load_replay(path: &str) -> Result<Replay, String> {
+    let data = fs::read_to_string(path).map_err(|e| format!("unable to read {}: {}", path, e))?;
+    let mut lines = data.lines();
+    let header: ReplayHeader = lines.next()
+        .ok_or_else(|| format!("{} is empty", path))
+        .and_then(|l| serde_json::from_str(l).map_err(|e| format!("bad replay header: {}", e)))?;
+    if header.version != REPLAY_VERSION {
+        return Err(format!("replay is version {}, this build reads version {}", header.version, REPLAY_VERSION));
+    }
+
+    let mut actions = Vec::new();
+    for line in lines.filter(|l| !l.trim().is_empty()) {
+        actions.push(serde_json::from_str(line).map_err(|e| format!("bad replay entry: {}", e))?);
+    }
+    Ok(Replay { seed: header.seed, builder: header.builder, actions })
+}
+
+/// Checks the world still matches the recording and, if so, hands back the action to perform.
+fn resolve(ecs: &World, recorded: &RecordedAction) -> Result<Action, Divergence> {
+    let found = TurnDigest::capture(ecs);
+    if found != recorded.before {
+        return Err(Divergence { turn: recorded.turn, expected: recorded.before.clone(), found: Mismatch::Digest(found) });
+    }
+    recorded.command.to_action(ecs)
+        .map_err(|reason| Divergence { turn: recorded.turn, expected: recorded.before.clone(), found: Mismatch::MissingItem(reason) })
+}
+
+/// Pulls the next action off the playback queue, if a replay is being watched.
+/// A divergence is logged and ends playback, handing control back to the keyboard.
+pub fn next_playback_action(ecs: &mut World) -> Option<Action> {
+    let recorded = {
+        let playback = ecs.try_fetch::<ReplayPlayback>()?;
+        playback.actions.get(playback.next)?.clone()
+    };
+
+    match resolve(ecs, &recorded) {
+        Ok(action) => {
+            ecs.fetch_mut::<ReplayPlayback>().next += 1;
+            Some(action)
+        }
+        Err(divergence) => {
+            rltk::console::log(divergence.to_string());
+            ecs.fetch_mut::<GameLog>().entries.push(format!("Replay diverged at turn {}", divergence.turn));
+            ecs.remove::<ReplayPlayback>();
+            None
+        }
+    }
+}
+
+/// Re-runs a recording without a window, returning how many turns were played.
+pub fn run_headless(replay: &Replay, raws: RawMaster) -> Result<usize, Divergence> {
+    let mut sim = Simulation::new(replay.seed, replay.builder, raws);
+    for recorded in replay.actions.iter() {
+        if !matches!(sim.runstate(), RunState::AwaitingInput | RunState::LevelUp) {
+            return Err(Divergence { turn: recorded.turn, expected: recorded.before.clone(), found: Mismatch::Digest(TurnDigest::capture(sim.ecs())) });
+        }
+        let action = resolve(sim.ecs(), recorded)?;
+        sim.step(action);
+    }
+    Ok(replay.actions.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{saveload_system, raws};
+
+    const ROUTE: [(i32, i32); 8] = [(1, 0), (1, 0), (0, 1), (-1, 0), (0, -1), (1, 1), (-1, -1), (0, 1)];
+
+    fn walk(sim: &mut Simulation, steps: usize) -> Vec<TurnDigest> {
+        let mut digests = Vec::new();
+        for (delta_x, delta_y) in ROUTE.iter().cycle().take(steps) {
+            sim.step(Action::Move { delta_x: *delta_x, delta_y: *delta_y });
+            digests.push(TurnDigest::capture(sim.ecs()));
+        }
+        digests
+    }
+
+    #[test]
+    fn a_reloaded_game_plays_out_the_same() {
+        for seed in 1..6 {
+            let mut original = Simulation::new(seed, None, raws::load_raws().unwrap());
+            walk(&mut original, 20);
+
+            let path = std::env::temp_dir().join(format!("roguelike-replay-test-{}-{}.json", std::process::id(), seed));
+            let path = path.to_str().unwrap();
+            saveload_system::save_to(&mut original.state.ecs, path);
+            // loaded over a different game, so the entity ids come out in a different order
+            let mut reloaded = Simulation::new(seed + 100, None, raws::load_raws().unwrap());
+            let ok = saveload_system::load_from(&mut reloaded.state.ecs, path);
+            fs::remove_file(path).unwrap();
+            assert_eq!(ok, Ok(()));
+            assert_eq!(TurnDigest::capture(original.ecs()), TurnDigest::capture(reloaded.ecs()));
+
+            assert_eq!(walk(&mut original, 60), walk(&mut reloaded, 60), "seed {}", seed);
+        }
+    }
+
+    fn record(seed: u64, steps: usize) -> Replay {
+        let mut sim = Simulation::new(seed, None, raws::load_raws().unwrap());
+        let mut actions = Vec::new();
+        for (turn, (delta_x, delta_y)) in ROUTE.iter().cycle().take(steps).enumerate() {
+            let before = TurnDigest::capture(sim.ecs());
+            actions.push(RecordedAction { turn: turn as u32, command: RecordedCommand::Move { delta_x: *delta_x, delta_y: *delta_y }, before });
+            sim.step(Action::Move { delta_x: *delta_x, delta_y: *delta_y });
+        }
+        Replay { seed, builder: None, actions }
+    }
+
+    #[test]
+    fn a_tampered_replay_is_caught_where_it_goes_wrong() {
+        let mut replay = record(3, 12);
+        assert_eq!(run_headless(&replay, raws::load_raws().unwrap()).unwrap(), 12);
+
+        replay.actions[7].before.player_hp += 1;
+        match run_headless(&replay, raws::load_raws().unwrap()) {
+            Err(Divergence { turn: 7, found: Mismatch::Digest(found), .. }) => assert_eq!(found.player_hp + 1, replay.actions[7].before.player_hp),
+            other => panic!("expected a divergence at turn 7, got {:?}", other),
+        }
+    }
+}
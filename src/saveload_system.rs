@@ -0,0 +1,308 @@
+use specs::prelude::*;
+use specs::saveload::{SimpleMarker, SimpleMarkerAllocator, SerializeComponents, DeserializeComponents, MarkerAllocator, ConvertSaveload, EntityData};
+use specs::error::NoError;
+use serde::{Serialize, Deserialize};
+use super::components::*;
+use super::{Map, MAPCOUNT, ACTION_COST, GameSeed, gamelog::GameLog, replay::ReplayRecorder, map_builders::BuilderChoice};
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs::File;
+use std::path::Path;
+use std::fs;
+
+const SAVE_PATH: &str = "./savegame.json";
+
+/// Bump this whenever a saved component or resource changes shape, older saves are refused rather than misread.
+pub const SAVE_VERSION: u32 = 18;
+
+#[derive(Serialize, Deserialize)]
+struct SaveHeader {
+    version: u32
+}
+
+macro_rules! serialize_individually {
+    ($ecs:expr, $ser:expr, $data:expr, $( $type:ty),*) => {
+        $(
+        SerializeComponents::<NoError, SimpleMarker<SerializeMe>>::serialize(
+            &( $ecs.read_storage::<$type>(), ),
+            &$data.0,
+            &$data.1,
+            &mut $ser,
+        )
+        .unwrap();
+        )*
+    };
+}
+
+macro_rules! deserialize_individually {
+    ($ecs:expr, $de:expr, $data:expr, $( $type:ty),*) => {
+        $(
+        DeserializeComponents::<NoError, _>::deserialize(
+            &mut ( &mut $ecs.write_storage::<$type>(), ),
+            &$data.0, // entities
+            &mut $data.1, // marker
+            &mut $data.2, // allocater
+            &mut $de,
+        )
+        .map_err(|e| format!("bad {} data: {}", stringify!($type), e))?;
+        )*
+    };
+}
+
+/// Reads each component list without touching the world, noting how many entities had one.
+/// Every saved entity turns up in every list, with `None` where it lacks that component.
+macro_rules! check_individually {
+    ($de:expr, $counts:expr, $( $type:ty),*) => {
+        $(
+        let entries = Vec::<EntityData<SimpleMarker<SerializeMe>, (Option<<$type as ConvertSaveload<SimpleMarker<SerializeMe>>>::Data>,)>>::deserialize(&mut $de)
+            .map_err(|e| format!("bad {} data: {}", stringify!($type), e))?;
+        $counts.push((stringify!($type), entries.iter().filter(|entry| entry.components.0.is_some()).count()));
+        )*
+    };
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn save_game(_ecs: &mut World) {}
+
+/// Writes every entity and the resources they depend on to the save file.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save_game(ecs: &mut World) {
+    save_to(ecs, SAVE_PATH);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn save_to(ecs: &mut World, path: &str) {
+    // an effect whose target has died only goes away on the next turn's tick, and there's nothing left to save it against
+    let orphaned: Vec<Entity> = {
+        let entities = ecs.entities();
+        let effects = ecs.read_storage::<StatusEffect>();
+        (&entities, &effects).join().filter(|(_, effect)| !entities.is_alive(effect.target)).map(|(e, _)| e).collect()
+    };
+    ecs.delete_entities(&orphaned).expect("Unable to delete status effect");
+
+    // make sure everything alive gets a marker, so references between entities survive the trip
+    {
+        let entities = ecs.entities();
+        let mut markers = ecs.write_storage::<SimpleMarker<SerializeMe>>();
+        let mut allocator = ecs.write_resource::<SimpleMarkerAllocator<SerializeMe>>();
+        for entity in entities.join() {
+            allocator.mark(entity, &mut markers);
+        }
+    }
+
+    // resources aren't serialized, so they ride along in a helper entity
+    let mapcopy = ecs.get_mut::<Map>().unwrap().clone();
+    let log = ecs.fetch::<GameLog>().entries.clone();
+    let seed = ecs.fetch::<GameSeed>().seed;
+    let rng = (*ecs.fetch::<rltk::RandomNumberGenerator>()).clone();
+    let replay = ecs.fetch::<ReplayRecorder>().actions.clone();
+    let builder = ecs.fetch::<BuilderChoice>().kind;
+    let savehelper = ecs.create_entity()
+        .with(SerializationHelper { map: mapcopy, log, seed, rng, replay, builder })
+        .build();
+    {
+        let mut markers = ecs.write_storage::<SimpleMarker<SerializeMe>>();
+        let mut allocator = ecs.write_resource::<SimpleMarkerAllocator<SerializeMe>>();
+        allocator.mark(savehelper, &mut markers);
+    }
+
+    {
+        let data = (ecs.entities(), ecs.read_storage::<SimpleMarker<SerializeMe>>());
+
+        let writer = File::create(path).unwrap();
+        let mut serializer = serde_json::Serializer::new(writer);
+        SaveHeader { version: SAVE_VERSION }.serialize(&mut serializer).unwrap();
+        serialize_individually!(ecs, serializer, data, Position, Renderable, Player, Viewshed, Monster, Brain, Faction, Ally, Energy,
+            Name, BlocksTile, CombatStats, Attributes, Skills, Experience, HungerClock, ProvidesFood, GivesExperience, WantsToMelee, Item, ProvidesHealing,
+            WantsToPickupItem, InBackpack, WantsToUse, WantsToDrop, Consumable, InflictDamage,
+            Ranged, Spell, AreaOfEffect, StatusEffect, InflictsStatus, Hidden, EntryTrigger, SingleActivation,
+            TeleportsVictim, Alarm, Alerted, BlocksVisibility, Door, Equippable, Equipped, MeleePowerBonus, AttackCost, DefenseBonus, SerializationHelper
+        );
+    }
+
+    ecs.delete_entity(savehelper).expect("Crash on cleanup");
+}
+
+pub fn does_save_exist() -> bool {
+    Path::new(SAVE_PATH).exists()
+}
+
+/// Replaces the current world with the one in the save file.
+/// Says why and leaves the world alone if the file can't be read, is damaged or was written by
+/// an incompatible version.
+pub fn load_game(ecs: &mut World) -> Result<(), String> {
+    load_from(ecs, SAVE_PATH)
+}
+
+pub(crate) fn load_from(ecs: &mut World, path: &str) -> Result<(), String> {
+    let data = fs::read_to_string(path).map_err(|e| format!("unable to read {}: {}", path, e))?;
+    check_save(&data)?;
+
+    // everything parsed, so it's safe to throw the current world away
+    let mut de = serde_json::Deserializer::from_str(&data);
+    SaveHeader::deserialize(&mut de).map_err(|e| format!("bad save header: {}", e))?;
+    ecs.delete_all();
+
+    {
+        let mut d = (&mut ecs.entities(), &mut ecs.write_storage::<SimpleMarker<SerializeMe>>(), &mut ecs.write_resource::<SimpleMarkerAllocator<SerializeMe>>());
+
+        deserialize_individually!(ecs, de, d, Position, Renderable, Player, Viewshed, Monster, Brain, Faction, Ally, Energy,
+            Name, BlocksTile, CombatStats, Attributes, Skills, Experience, HungerClock, ProvidesFood, GivesExperience, WantsToMelee, Item, ProvidesHealing,
+            WantsToPickupItem, InBackpack, WantsToUse, WantsToDrop, Consumable, InflictDamage,
+            Ranged, Spell, AreaOfEffect, StatusEffect, InflictsStatus, Hidden, EntryTrigger, SingleActivation,
+            TeleportsVictim, Alarm, Alerted, BlocksVisibility, Door, Equippable, Equipped, MeleePowerBonus, AttackCost, DefenseBonus, SerializationHelper
+        );
+    }
+
+    let mut deleteme: Option<Entity> = None;
+    {
+        let entities = ecs.entities();
+        let helper = ecs.read_storage::<SerializationHelper>();
+        let player = ecs.read_storage::<Player>();
+        let position = ecs.read_storage::<Position>();
+        for (e, h) in (&entities, &helper).join() {
+            let mut worldmap = ecs.write_resource::<Map>();
+            *worldmap = h.map.clone();
+            worldmap.tile_content = vec![Vec::new(); MAPCOUNT];
+            ecs.write_resource::<GameLog>().entries = h.log.clone();
+            ecs.write_resource::<GameSeed>().seed = h.seed;
+            *ecs.write_resource::<rltk::RandomNumberGenerator>() = h.rng.clone();
+            ecs.write_resource::<ReplayRecorder>().actions = h.replay.clone();
+            ecs.write_resource::<BuilderChoice>().kind = h.builder;
+            deleteme = Some(e);
+        }
+        for (e, _p, pos) in (&entities, &player, &position).join() {
+            let mut ppos = ecs.write_resource::<rltk::Point>();
+            *ppos = rltk::Point::new(pos.x, pos.y);
+            let mut player_resource = ecs.write_resource::<Entity>();
+            *player_resource = e;
+        }
+    }
+    let helper = deleteme.ok_or_else(|| "save has no game state".to_string())?;
+    ecs.delete_entity(helper).expect("Unable to delete helper");
+
+    // a speed that can't go up would leave the scheduler waiting forever on that entity
+    for energy in (&mut ecs.write_storage::<Energy>()).join() {
+        if energy.speed <= 0 {
+            rltk::console::log(format!("WARNING - save has an entity with speed {}, resetting it to {}", energy.speed, ACTION_COST));
+            energy.speed = ACTION_COST;
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses a whole save without loading it, so a damaged file is turned away before the
+/// world it would replace is gone.
+fn check_save(data: &str) -> Result<(), String> {
+    let mut de = serde_json::Deserializer::from_str(data);
+    let header = SaveHeader::deserialize(&mut de).map_err(|e| format!("bad save header: {}", e))?;
+    if header.version != SAVE_VERSION {
+        return Err(format!("save is version {}, this build reads version {}", header.version, SAVE_VERSION));
+    }
+
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    check_individually!(de, counts, Position, Renderable, Player, Viewshed, Monster, Brain, Faction, Ally, Energy,
+            Name, BlocksTile, CombatStats, Attributes, Skills, Experience, HungerClock, ProvidesFood, GivesExperience, WantsToMelee, Item, ProvidesHealing,
+            WantsToPickupItem, InBackpack, WantsToUse, WantsToDrop, Consumable, InflictDamage,
+            Ranged, Spell, AreaOfEffect, StatusEffect, InflictsStatus, Hidden, EntryTrigger, SingleActivation,
+            TeleportsVictim, Alarm, Alerted, BlocksVisibility, Door, Equippable, Equipped, MeleePowerBonus, AttackCost, DefenseBonus, SerializationHelper
+    );
+    de.end().map_err(|e| format!("unexpected data after the save: {}", e))?;
+
+    let count = |name: &str| counts.iter().find(|(n, _)| *n == name).map(|(_, c)| *c).unwrap_or(0);
+    if count("SerializationHelper") != 1 || count("Player") != 1 {
+        return Err("save has no game state or no player".to_string());
+    }
+    Ok(())
+}
+
+pub fn delete_save() {
+    if does_save_exist() { fs::remove_file(SAVE_PATH).expect("Unable to delete file"); }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{State, raws};
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let mut original = State::new_game(7, None, false, raws::load_raws().unwrap());
+        let path = std::env::temp_dir().join(format!("roguelike-save-test-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        save_to(&mut original.ecs, path);
+
+        // a different seed, so anything not restored from the file shows up as a difference
+        let mut loaded = State::new_game(8, None, false, raws::load_raws().unwrap());
+        let ok = load_from(&mut loaded.ecs, path);
+        fs::remove_file(path).unwrap();
+        assert_eq!(ok, Ok(()));
+
+        let player_of = |ecs: &World| {
+            let player = *ecs.fetch::<Entity>();
+            let pos = ecs.read_storage::<Position>().get(player).map(|p| (p.x, p.y));
+            let hp = ecs.read_storage::<CombatStats>().get(player).map(|s| (s.hp, s.max_hp));
+            (pos, hp, *ecs.fetch::<rltk::Point>())
+        };
+        assert_eq!(player_of(&original.ecs), player_of(&loaded.ecs));
+
+        let original_map = original.ecs.fetch::<Map>();
+        let loaded_map = loaded.ecs.fetch::<Map>();
+        assert_eq!(original_map.depth, loaded_map.depth);
+        assert!(original_map.tiles == loaded_map.tiles);
+        assert_eq!(original_map.revealed_tiles, loaded_map.revealed_tiles);
+
+        assert_eq!(original.ecs.fetch::<GameSeed>().seed, loaded.ecs.fetch::<GameSeed>().seed);
+        let mut original_rng = original.ecs.fetch_mut::<rltk::RandomNumberGenerator>();
+        let mut loaded_rng = loaded.ecs.fetch_mut::<rltk::RandomNumberGenerator>();
+        for _ in 0..10 {
+            assert_eq!(original_rng.next_u64(), loaded_rng.next_u64());
+        }
+    }
+
+    #[test]
+    fn a_damaged_save_leaves_the_world_alone() {
+        let mut original = State::new_game(7, None, false, raws::load_raws().unwrap());
+        let path = std::env::temp_dir().join(format!("roguelike-damaged-test-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        save_to(&mut original.ecs, path);
+        let data = fs::read_to_string(path).unwrap();
+        fs::write(path, &data[..data.len() / 2]).unwrap();
+
+        let mut gs = State::new_game(8, None, false, raws::load_raws().unwrap());
+        let entities_before = gs.ecs.entities().join().count();
+        let player_before = *gs.ecs.fetch::<rltk::Point>();
+        let result = load_from(&mut gs.ecs, path);
+        fs::remove_file(path).unwrap();
+
+        assert!(result.is_err());
+        assert_eq!(gs.ecs.entities().join().count(), entities_before);
+        assert_eq!(*gs.ecs.fetch::<rltk::Point>(), player_before);
+        assert!(load_from(&mut gs.ecs, path).is_err(), "a missing file is an error too");
+    }
+
+    #[test]
+    fn saving_after_the_caster_and_victim_are_gone() {
+        let mut gs = State::new_game(7, None, false, raws::load_raws().unwrap());
+        let player = *gs.ecs.fetch::<Entity>();
+        let caster = gs.ecs.create_entity().with(Name { name: "Orc Shaman".to_string() }).build();
+        let victim = gs.ecs.create_entity().with(Name { name: "Goblin".to_string() }).build();
+        let poison = StatusEffectKind::Poison { damage: 1 };
+        gs.ecs.create_entity().with(StatusEffect { target: player, source: Some(caster), kind: poison, turns: 5 }).build();
+        gs.ecs.create_entity().with(StatusEffect { target: victim, source: Some(player), kind: poison, turns: 5 }).build();
+        gs.ecs.delete_entities(&[caster, victim]).unwrap();
+
+        let path = std::env::temp_dir().join(format!("roguelike-orphan-test-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        save_to(&mut gs.ecs, path);
+        let ok = load_from(&mut gs.ecs, path);
+        fs::remove_file(path).unwrap();
+        assert_eq!(ok, Ok(()));
+
+        let player = *gs.ecs.fetch::<Entity>();
+        let effects: Vec<StatusEffect> = gs.ecs.read_storage::<StatusEffect>().join().cloned().collect();
+        assert_eq!(effects.len(), 1);
+        assert_eq!(effects[0].target, player);
+        assert_eq!(effects[0].source, None);
+    }
+}
@@ -0,0 +1,146 @@
+use rltk::Point;
+use specs::prelude::*;
+use serde::{Serialize, Deserialize};
+use super::{State, RunState, Energy, ACTION_COST, WantsToUse, WantsToDrop, Map, TileType, gamelog::GameLog, player::{try_move_player, get_item, level_up}, raws, replay, map_builders::BuilderKind};
+
+/// Things the debug keys can drop under the mouse.
+#[derive(PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum Spawnable { Fireball, MagicMissile, HealthPotion }
+
+/// Which attribute a level up goes into.
+#[derive(PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum LevelUpChoice { Might, Fitness, Quickness, Intelligence }
+
+/// A single player command, independent of whether it came from the keyboard, a menu or a script.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Action {
+    Move { delta_x: i32, delta_y: i32 },
+    PickUp,
+    UseItem { item: Entity, target: Option<Point> },
+    DropItem { item: Entity },
+    Spawn { spawnable: Spawnable, x: i32, y: i32 },
+    Descend,
+    LevelUp { choice: LevelUpChoice },
+}
+
+impl State {
+    /// Queues up the intent for an action and hands back the state the game moves into.
+    /// Every action goes through here, so this is also where it gets recorded.
+    pub fn perform(&mut self, action: Action) -> RunState {
+        replay::record(&mut self.ecs, action);
+        match action {
+            Action::Move { delta_x, delta_y } => try_move_player(delta_x, delta_y, &mut self.ecs),
+            Action::PickUp => get_item(&mut self.ecs),
+            Action::UseItem { item, target } => {
+                let mut intent = self.ecs.write_storage::<WantsToUse>();
+                intent.insert(*self.ecs.fetch::<Entity>(), WantsToUse { item, target }).expect("Unable to insert intent");
+            }
+            Action::DropItem { item } => {
+                let mut intent = self.ecs.write_storage::<WantsToDrop>();
+                intent.insert(*self.ecs.fetch::<Entity>(), WantsToDrop { item }).expect("Unable to insert intent");
+            }
+            Action::Spawn { spawnable, x, y } => {
+                let name = match spawnable {
+                    Spawnable::Fireball => "Scroll of Fireball",
+                    Spawnable::MagicMissile => "Scroll of Magic Missile",
+                    Spawnable::HealthPotion => "Health Potion",
+                };
+                if let Err(e) = raws::spawn_named_entity(&mut self.ecs, name, x, y) {
+                    rltk::console::log(format!("WARNING - {}", e));
+                }
+            }
+            Action::Descend => {
+                let player_pos = *self.ecs.fetch::<Point>();
+                let map = self.ecs.fetch::<Map>();
+                let player_idx = map.xy_idx(player_pos.x, player_pos.y);
+                if map.tiles[player_idx] == TileType::DownStairs {
+                    return RunState::NextLevel;
+                }
+                let mut gamelog = self.ecs.fetch_mut::<GameLog>();
+                gamelog.entries.push("There is no way down from here.".to_string());
+                return RunState::AwaitingInput;
+            }
+            // levelling up doesn't cost a turn
+            Action::LevelUp { choice } => {
+                level_up(&mut self.ecs, choice);
+                return if self.level_up_pending() { RunState::LevelUp } else { RunState::AwaitingInput };
+            }
+        }
+
+        // whatever the player did, it cost them an action's worth of energy
+        let player_entity = *self.ecs.fetch::<Entity>();
+        if let Some(energy) = self.ecs.write_storage::<Energy>().get_mut(player_entity) {
+            energy.energy -= ACTION_COST;
+        }
+        RunState::PlayerTurn
+    }
+}
+
+/// Drives the game without a window: no rltk context, no rendering, just the RunState machine.
+pub struct Simulation {
+    pub state: State,
+}
+
+impl Simulation {
+    pub fn new(seed: u64, builder: Option<BuilderKind>, raws: raws::RawMaster) -> Simulation {
+        let mut sim = Simulation { state: State::new_game(seed, builder, false, raws) };
+        sim.settle();
+        sim
+    }
+
+    pub fn ecs(&self) -> &World {
+        &self.state.ecs
+    }
+
+    pub fn runstate(&self) -> RunState {
+        self.state.runstate()
+    }
+
+    /// Performs one player action and runs the world until it's waiting on the player again.
+    /// Actions sent while the game isn't waiting for input are ignored.
+    pub fn step(&mut self, action: Action) -> RunState {
+        if !matches!(self.runstate(), RunState::AwaitingInput | RunState::LevelUp) {
+            return self.runstate();
+        }
+        let newrunstate = self.state.perform(action);
+        self.state.set_runstate(newrunstate);
+        self.settle();
+        self.runstate()
+    }
+
+    fn settle(&mut self) {
+        let mut runstate = self.runstate();
+        while matches!(runstate, RunState::PreRun | RunState::PlayerTurn | RunState::Ticking | RunState::NextLevel) {
+            runstate = self.state.advance(runstate);
+            self.state.set_runstate(runstate);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replay::TurnDigest;
+
+    /// Walks a fixed route and notes the digest after every step.
+    fn digests(seed: u64) -> Vec<TurnDigest> {
+        let mut sim = Simulation::new(seed, None, raws::load_raws().unwrap());
+        let route = [(1, 0), (1, 0), (0, 1), (-1, 0), (0, -1), (1, 1), (-1, -1), (0, 1)];
+        let mut digests = vec![TurnDigest::capture(sim.ecs())];
+        for (delta_x, delta_y) in route.iter().cycle().take(60) {
+            sim.step(Action::Move { delta_x: *delta_x, delta_y: *delta_y });
+            digests.push(TurnDigest::capture(sim.ecs()));
+        }
+        digests
+    }
+
+    #[test]
+    fn same_seed_same_game() {
+        assert_eq!(digests(42), digests(42));
+    }
+
+    #[test]
+    fn different_seeds_differ() {
+        assert_ne!(digests(42), digests(43));
+    }
+}
@@ -0,0 +1,89 @@
+use rltk::RandomNumberGenerator;
+
+pub struct SpawnEntry {
+    pub name: String,
+    pub weight: i32,
+    pub min_depth: i32,
+    pub max_depth: i32,
+}
+
+/// A weighted list of what can spawn, each entry limited to a band of depths.
+#[derive(Default)]
+pub struct SpawnTable {
+    entries: Vec<SpawnEntry>,
+}
+
+impl SpawnTable {
+    pub fn new() -> SpawnTable {
+        SpawnTable { entries: Vec::new() }
+    }
+
+    pub fn add<S: ToString>(mut self, name: S, weight: i32, min_depth: i32, max_depth: i32) -> SpawnTable {
+        if weight > 0 {
+            self.entries.push(SpawnEntry { name: name.to_string(), weight, min_depth, max_depth });
+        }
+        self
+    }
+
+    /// Picks a name by weight from the entries allowed at `depth`, or `None` if nothing is.
+    pub fn roll(&self, depth: i32, rng: &mut RandomNumberGenerator) -> Option<&str> {
+        let available: Vec<&SpawnEntry> = self.entries.iter()
+            .filter(|e| depth >= e.min_depth && depth <= e.max_depth)
+            .collect();
+        let total_weight: i32 = available.iter().map(|e| e.weight).sum();
+        if total_weight == 0 { return None; }
+
+        let mut roll = rng.roll_dice(1, total_weight) - 1;
+        for entry in available.iter() {
+            if roll < entry.weight {
+                return Some(&entry.name);
+            }
+            roll -= entry.weight;
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> SpawnTable {
+        SpawnTable::new()
+            .add("Shallow", 1, 1, 3)
+            .add("Common", 3, 2, 5)
+            .add("Rare", 1, 2, 5)
+            .add("Deep", 1, 6, 9)
+            .add("Never", 0, 1, 9)
+    }
+
+    #[test]
+    fn roll_only_picks_entries_in_the_depth_band() {
+        let table = table();
+        let mut rng = RandomNumberGenerator::seeded(1);
+        for _ in 0..200 {
+            assert_eq!(table.roll(1, &mut rng), Some("Shallow"));
+            assert!(matches!(table.roll(4, &mut rng), Some("Common") | Some("Rare")));
+            assert_eq!(table.roll(9, &mut rng), Some("Deep"));
+        }
+        assert_eq!(table.roll(10, &mut rng), None);
+        assert_eq!(SpawnTable::new().roll(1, &mut rng), None);
+    }
+
+    #[test]
+    fn roll_follows_the_weights() {
+        let table = table();
+        let mut rng = RandomNumberGenerator::seeded(2);
+        let (mut common, mut rare) = (0, 0);
+        for _ in 0..4000 {
+            match table.roll(4, &mut rng) {
+                Some("Common") => common += 1,
+                Some("Rare") => rare += 1,
+                other => panic!("unexpected roll {:?}", other),
+            }
+        }
+        // 3 to 1, with plenty of slack for the dice
+        assert!(common > rare * 2 && common < rare * 4, "common {} rare {}", common, rare);
+    }
+}
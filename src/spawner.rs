@@ -1,14 +1,13 @@
 use rltk::{RGB, RandomNumberGenerator};
 use specs::prelude::*;
-use crate::AreaOfEffect;
 
-use super::{CombatStats, Player, Renderable, Name, Position, Viewshed, Monster, BlocksTile, Rect, Item, ProvidesHealing, map::MAPWIDTH, Consumable, InflictDamage, Ranged};
+use super::{CombatStats, Faction, Energy, ACTION_COST, Attributes, Skills, Experience, HungerClock, HungerState, hunger_system::WELL_FED_DURATION, gamesystem, Player, Renderable, Name, Position, Viewshed, Rect, Map, TileType, map::MAPWIDTH, raws::{spawn_named_entity, RawMaster}};
 
-const MAX_MONSTERS: i32 = 4;
-const MAX_ITEMS: i32 = 2;
+const MAX_SPAWNS: i32 = 4;
 
 /// spawns the player at a specified location
 pub fn player(ecs: &mut World, player_x: i32, player_y: i32) -> Entity {
+    let attributes = Attributes { might: 14, fitness: 15, quickness: 12, intelligence: 10 };
     ecs.create_entity()
         .with(Position { x: player_x, y: player_y })
         .with(Renderable {
@@ -20,158 +19,68 @@ pub fn player(ecs: &mut World, player_x: i32, player_y: i32) -> Entity {
         .with(Player{})
         .with(Viewshed{ visible_tiles : Vec::new(), range: 8, dirty: true  })
         .with(Name{name: "Player".to_string()})
-        .with(CombatStats {max_hp: 30, hp: 30, defense: 2, power: 17})
+        .with(Faction{name: "Player".to_string()})
+        .with(CombatStats {max_hp: gamesystem::max_hp(&attributes, 1), hp: gamesystem::max_hp(&attributes, 1), damage: rltk::parse_dice_string("1d6").unwrap()})
+        .with(attributes)
+        .with(Skills { melee: 0, dodge: 1, magic: 0 })
+        .with(Experience{ level: 1, xp: 0 })
+        .with(HungerClock{ state: HungerState::WellFed, duration: WELL_FED_DURATION })
+        .with(Energy{ speed: 100, energy: ACTION_COST })
         .build()
 }
 
-/// spawns random monsters at a specified location
-pub fn random_monster(ecs: &mut World, x: i32, y: i32) {
-    let roll: i32;
-    {
-        let mut rng = ecs.write_resource::<RandomNumberGenerator>();
-        roll = rng.roll_dice(1,2);
+/// Fills a room with stuff, deeper levels get more of it
+pub fn spawn_room(ecs: &mut World, map: &Map, room: &Rect, map_depth: i32) {
+    let mut possible_targets: Vec<usize> = Vec::new();
+    for y in room.y1 + 1 ..= room.y2 {
+        for x in room.x1 + 1 ..= room.x2 {
+            let idx = map.xy_idx(x, y);
+            if map.tiles[idx] == TileType::Floor {
+                possible_targets.push(idx);
+            }
+        }
     }
 
-    match roll {
-        1 => { orc(ecs, x, y)},
-        _ => { goblin(ecs, x, y)}
-    }
+    spawn_region(ecs, &possible_targets, map_depth);
 }
 
-pub fn orc(ecs: &mut World, x: i32 , y: i32) {monster(ecs,x,y,rltk::to_cp437('o'), "Orc");}
-pub fn goblin(ecs: &mut World, x: i32 , y: i32) {monster(ecs,x,y,rltk::to_cp437('g'), "Goblin");}
-
-fn monster<S: ToString>(ecs: &mut World, x: i32, y: i32, glyph: rltk::FontCharType, name: S) {
-    ecs.create_entity()
-            .with(Position{x,y})
-            .with(Renderable{
-                glyph: glyph,
-                fg: RGB::named(rltk::RED),
-                bg: RGB::named(rltk::BLACK),
-                render_order: 1
-            })
-            .with(Viewshed{ visible_tiles : Vec::new(), range: 8, dirty: true})
-            .with(Monster{})
-            .with(Name{name: name.to_string()})
-            .with(BlocksTile{})
-            .with(CombatStats {max_hp: 16, hp: 16, defense: 1, power: 2})
-            .build();
+/// Hangs a closed door on each of the given tiles.
+pub fn spawn_doors(ecs: &mut World, map: &Map, doors: &[usize]) {
+    for idx in doors.iter() {
+        let x = *idx as i32 % map.width;
+        let y = *idx as i32 / map.width;
+        if let Err(e) = spawn_named_entity(ecs, "Door", x, y) {
+            rltk::console::log(format!("WARNING - {}", e));
+        }
+    }
 }
 
-/// Fills a room with stuff
-pub fn spawn_room(ecs: &mut World, room: &Rect) {
-    let mut monster_spawn_points: Vec<usize> = Vec::new();
-    let mut item_spawn_points: Vec<usize> = Vec::new();
+/// Fills any set of tiles with stuff from the spawn table, for maps that don't have rooms
+pub fn spawn_region(ecs: &mut World, area: &[usize], map_depth: i32) {
+    let mut spawn_points: Vec<(usize, String)> = Vec::new();
+    let mut areas: Vec<usize> = Vec::from(area);
 
     {
+        let raws = ecs.fetch::<RawMaster>();
         let mut rng = ecs.write_resource::<RandomNumberGenerator>();
-        let num_monsters = rng.roll_dice(1, MAX_MONSTERS + 2) - 3;
-        let num_items = rng.roll_dice(1, MAX_ITEMS + 2) -3;
-
-        for _i in 0.. num_monsters {
-            let mut added = false;
-            while !added {
-                let x = (room.x1 + rng.roll_dice(1, i32::abs(room.x2 - room.x1))) as usize;
-                let y = (room.y1 + rng.roll_dice(1, i32::abs(room.y2 - room.y1))) as usize;
-                let idx = (y * MAPWIDTH) + x;
-                if !monster_spawn_points.contains(&idx) {
-                    monster_spawn_points.push(idx);
-                    added = true;
-                }
-            }
-        }
-        
-        for _i in 0 .. num_items {
-            let mut added = false; 
-            while !added {
-                let x = (room.x1 + rng.roll_dice(1, i32::abs(room.x2 - room.x1))) as usize;
-                let y = (room.y1 + rng.roll_dice(1, i32::abs(room.y2 - room.y1))) as usize;
-                let idx = (y * MAPWIDTH) + x;
-                if !item_spawn_points.contains(&idx) {
-                    item_spawn_points.push(idx);
-                    added = true;
-                }
+        // never ask for more spots than the area has
+        let num_spawns = i32::min(areas.len() as i32, rng.roll_dice(1, MAX_SPAWNS + 3) + (map_depth - 1) - 3);
+
+        for _i in 0 .. num_spawns {
+            let array_index = if areas.len() == 1 { 0usize } else { (rng.roll_dice(1, areas.len() as i32) - 1) as usize };
+            let map_idx = areas[array_index];
+            if let Some(name) = raws.spawn_table().roll(map_depth, &mut rng) {
+                spawn_points.push((map_idx, name.to_string()));
             }
-    
+            areas.remove(array_index);
         }
     }
 
-    //spawn monsters
-    for idx in monster_spawn_points.iter() {
-        let x = *idx % MAPWIDTH;
-        let y = *idx / MAPWIDTH;
-        random_monster(ecs, x as i32, y as i32);
-    }
-
-    for idx in item_spawn_points.iter() {
-        let x = *idx % MAPWIDTH;
-        let y = *idx / MAPWIDTH;
-        random_item(ecs, x as i32, y as i32)
-    }
-
-}
-
-fn random_item(ecs: &mut World, x: i32, y: i32) {
-    let roll: i32;
-    {
-        let mut rng = ecs.write_resource::<RandomNumberGenerator>();
-        roll = rng.roll_dice(1, 3);
-    }
-
-    match roll {
-        1 => { health_potion(ecs, x, y)}
-        2 => { magic_missile(ecs, x, y)}
-        _ => { fireball(ecs, x, y)}
+    for (idx, name) in spawn_points.iter() {
+        let x = (*idx % MAPWIDTH) as i32;
+        let y = (*idx / MAPWIDTH) as i32;
+        if let Err(e) = spawn_named_entity(ecs, name, x, y) {
+            rltk::console::log(format!("WARNING - {}", e));
+        }
     }
 }
-
-pub fn health_potion(ecs: &mut World, x: i32, y: i32) { 
-    ecs.create_entity()
-    .with(Position {x, y})
-    .with(Renderable {
-        glyph: rltk::to_cp437('¡'),
-        fg: RGB::named(rltk::MAGENTA),
-        bg: RGB::named(rltk::BLACK),
-        render_order: 2
-    })
-    .with(Name {name: "Health Potion".to_string()})
-    .with(Item{})
-    .with(Consumable{})
-    .with(ProvidesHealing{heal_amount: 8})
-    .build();
-}
-
-pub fn magic_missile(ecs: &mut World, x:i32, y:i32) {
-    ecs.create_entity()
-    .with(Position {x, y})
-    .with(Renderable {
-        glyph: rltk::to_cp437(')'),
-        fg: RGB::named(rltk::CYAN),
-        bg: RGB::named(rltk::BLACK),
-        render_order: 2
-    })
-    .with(Name {name: "Scroll of Magic Missile".to_string()})
-    .with(Item{})
-    .with(Consumable{})
-    .with(Ranged{ range: 6})
-    .with(InflictDamage{ damage: 17})
-    .build();    
-}
-
-pub fn fireball(ecs: &mut World, x:i32, y:i32) {
-    ecs.create_entity()
-    .with(Position {x, y})
-    .with(Renderable {
-        glyph: rltk::to_cp437(')'),
-        fg: RGB::named(rltk::ORANGE),
-        bg: RGB::named(rltk::BLACK),
-        render_order: 2
-    })
-    .with(Name {name: "Scroll of Fireball".to_string()})
-    .with(Item{})
-    .with(Consumable{})
-    .with(Ranged{ range: 6})
-    .with(InflictDamage{ damage: 20})
-    .with(AreaOfEffect{radius: 3})
-    .build();    
-}
\ No newline at end of file
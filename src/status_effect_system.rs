@@ -0,0 +1,164 @@
+use specs::prelude::*;
+use super::{StatusEffect, StatusEffectKind, CombatStats, SufferDamage, Name, RunState, TurnClock, events::{EventQueue, GameEvent, DamageKind}};
+
+/// Ticks every status effect once per turn and removes the ones that have run out.
+pub struct StatusEffectSystem {}
+
+impl<'a> System<'a> for StatusEffectSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        Entities<'a>,
+        ReadExpect<'a, RunState>,
+        ReadExpect<'a, TurnClock>,
+        WriteExpect<'a, EventQueue>,
+        WriteStorage<'a, StatusEffect>,
+        WriteStorage<'a, CombatStats>,
+        WriteStorage<'a, SufferDamage>,
+        ReadStorage<'a, Name>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (entities, runstate, clock, mut events, mut status_effects, mut combat_stats, mut suffer_damage, names) = data;
+
+        if *runstate != RunState::Ticking || !clock.new_turn {return;}
+
+        let mut expired: Vec<Entity> = Vec::new();
+        for (effect_entity, effect) in (&entities, &mut status_effects).join() {
+            // the target died or left the level without it
+            if !entities.is_alive(effect.target) || combat_stats.get(effect.target).is_none() {
+                expired.push(effect_entity);
+                continue;
+            }
+
+            match effect.kind {
+                StatusEffectKind::Confusion => {}
+                StatusEffectKind::Poison{ damage } => {
+                    SufferDamage::new_damage(&mut suffer_damage, effect.target, damage, effect.source);
+                    events.emit(GameEvent::DamageDealt {
+                        source: effect.source,
+                        source_name: effect.source.and_then(|source| names.get(source)).map(|n| n.name.clone()).unwrap_or_default(),
+                        target: effect.target,
+                        target_name: names.get(effect.target).map(|n| n.name.clone()).unwrap_or_default(),
+                        amount: damage,
+                        kind: DamageKind::Poison
+                    });
+                }
+                StatusEffectKind::Regeneration{ amount } => {
+                    let stats = combat_stats.get_mut(effect.target).unwrap();
+                    stats.hp = i32::min(stats.max_hp, stats.hp + amount);
+                }
+            }
+
+            effect.turns -= 1;
+            if effect.turns < 1 {
+                expired.push(effect_entity);
+                let target_name = names.get(effect.target).map(|n| n.name.clone()).unwrap_or_default();
+                events.emit(GameEvent::StatusExpired { target: effect.target, target_name, kind: effect.kind });
+            }
+        }
+
+        for effect_entity in expired.iter() {
+            entities.delete(*effect_entity).expect("Unable to delete status effect");
+        }
+    }
+}
+
+/// Puts `kind` on `target` for `turns` turns, topping up the duration if it already has that effect.
+/// Anything the effect ends up doing is credited to `source`.
+pub fn apply_status_effect(entities: &Entities, status_effects: &mut WriteStorage<StatusEffect>, target: Entity, source: Entity, kind: StatusEffectKind, turns: i32) {
+    for effect in (&mut *status_effects).join() {
+        if effect.target == target && std::mem::discriminant(&effect.kind) == std::mem::discriminant(&kind) {
+            effect.kind = kind;
+            effect.source = Some(source);
+            effect.turns = i32::max(effect.turns, turns);
+            return;
+        }
+    }
+
+    entities.build_entity()
+        .with(StatusEffect{ target, source: Some(source), kind, turns }, status_effects)
+        .build();
+}
+
+/// Whether `target` currently has an effect of the same kind as `kind`.
+pub fn has_status_effect(status_effects: &ReadStorage<StatusEffect>, target: Entity, kind: StatusEffectKind) -> bool {
+    status_effects.join().any(|effect| effect.target == target && std::mem::discriminant(&effect.kind) == std::mem::discriminant(&kind))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{State, raws};
+
+    fn apply(gs: &mut State, target: Entity, source: Entity, kind: StatusEffectKind, turns: i32) {
+        let entities = gs.ecs.entities();
+        let mut status_effects = gs.ecs.write_storage::<StatusEffect>();
+        apply_status_effect(&entities, &mut status_effects, target, source, kind, turns);
+    }
+
+    fn tick(gs: &mut State, new_turn: bool) {
+        gs.ecs.write_resource::<TurnClock>().new_turn = new_turn;
+        StatusEffectSystem{}.run_now(&gs.ecs);
+        gs.ecs.maintain();
+    }
+
+    #[test]
+    fn poison_hurts_each_turn_until_it_runs_out() {
+        let mut gs = State::new_game(1, None, false, raws::load_raws().unwrap());
+        let goblin = raws::spawn_named_entity(&mut gs.ecs, "Goblin", 1, 1).unwrap();
+        let orc = raws::spawn_named_entity(&mut gs.ecs, "Orc", 2, 1).unwrap();
+        *gs.ecs.write_resource::<RunState>() = RunState::Ticking;
+        gs.ecs.write_resource::<EventQueue>().clear();
+        apply(&mut gs, goblin, orc, StatusEffectKind::Poison { damage: 2 }, 2);
+
+        tick(&mut gs, false);
+        assert!(gs.ecs.read_storage::<SufferDamage>().get(goblin).is_none(), "only a new turn ticks it");
+
+        tick(&mut gs, true);
+        tick(&mut gs, true);
+        let damage = gs.ecs.read_storage::<SufferDamage>().get(goblin).unwrap().amount.clone();
+        assert_eq!(damage, vec![(2, Some(orc)), (2, Some(orc))]);
+        assert_eq!(gs.ecs.read_storage::<StatusEffect>().join().count(), 0);
+        assert!(gs.ecs.fetch::<EventQueue>().iter().any(|event| matches!(event,
+            GameEvent::StatusExpired { target, kind: StatusEffectKind::Poison{..}, .. } if *target == goblin)));
+    }
+
+    #[test]
+    fn applying_again_tops_up_instead_of_stacking() {
+        let mut gs = State::new_game(1, None, false, raws::load_raws().unwrap());
+        let player = *gs.ecs.fetch::<Entity>();
+        let goblin = raws::spawn_named_entity(&mut gs.ecs, "Goblin", 1, 1).unwrap();
+        apply(&mut gs, player, goblin, StatusEffectKind::Confusion, 4);
+        apply(&mut gs, player, goblin, StatusEffectKind::Confusion, 2);
+        apply(&mut gs, player, goblin, StatusEffectKind::Regeneration { amount: 1 }, 2);
+
+        let status_effects = gs.ecs.read_storage::<StatusEffect>();
+        let confusion: Vec<i32> = status_effects.join()
+            .filter(|effect| effect.kind == StatusEffectKind::Confusion)
+            .map(|effect| effect.turns)
+            .collect();
+        assert_eq!(confusion, vec![4]);
+        assert!(has_status_effect(&status_effects, player, StatusEffectKind::Confusion));
+        assert!(!has_status_effect(&status_effects, goblin, StatusEffectKind::Confusion));
+        assert_eq!(status_effects.join().count(), 2);
+    }
+
+    #[test]
+    fn regeneration_stops_at_full_health() {
+        let mut gs = State::new_game(1, None, false, raws::load_raws().unwrap());
+        let player = *gs.ecs.fetch::<Entity>();
+        *gs.ecs.write_resource::<RunState>() = RunState::Ticking;
+        let max_hp = {
+            let mut stats = gs.ecs.write_storage::<CombatStats>();
+            let stats = stats.get_mut(player).unwrap();
+            stats.hp = stats.max_hp - 3;
+            stats.max_hp
+        };
+        apply(&mut gs, player, player, StatusEffectKind::Regeneration { amount: 2 }, 5);
+
+        tick(&mut gs, true);
+        assert_eq!(gs.ecs.read_storage::<CombatStats>().get(player).unwrap().hp, max_hp - 1);
+        tick(&mut gs, true);
+        assert_eq!(gs.ecs.read_storage::<CombatStats>().get(player).unwrap().hp, max_hp);
+    }
+}
@@ -0,0 +1,259 @@
+use specs::prelude::*;
+use rltk::{Point, RandomNumberGenerator};
+use super::{Map, TileType, Position, Viewshed, Name, Monster, BlocksTile, Hidden, EntryTrigger, EntityMoved, InflictDamage, SufferDamage,
+    TeleportsVictim, Alarm, Alerted, SingleActivation, Attributes, RunState, sort_by_position, gamesystem, events::{EventQueue, GameEvent, DamageKind}};
+
+/// What a search roll has to reach to spot something hidden.
+const SEARCH_DIFFICULTY: i32 = 15;
+/// How close something hidden has to be before the player gets a roll to notice it.
+const SEARCH_RADIUS: f32 = 3.0;
+
+/// Sets off any traps on the tiles that things moved onto this turn.
+pub struct TriggerSystem {}
+
+impl<'a> System<'a> for TriggerSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        Entities<'a>,
+        WriteExpect<'a, Map>,
+        ReadExpect<'a, Entity>,
+        WriteExpect<'a, Point>,
+        WriteExpect<'a, RandomNumberGenerator>,
+        WriteExpect<'a, EventQueue>,
+        WriteStorage<'a, EntityMoved>,
+        WriteStorage<'a, Position>,
+        WriteStorage<'a, Viewshed>,
+        WriteStorage<'a, Hidden>,
+        WriteStorage<'a, SufferDamage>,
+        WriteStorage<'a, Alerted>,
+        (ReadStorage<'a, EntryTrigger>,
+         ReadStorage<'a, Name>,
+         ReadStorage<'a, Monster>,
+         ReadStorage<'a, BlocksTile>,
+         ReadStorage<'a, InflictDamage>,
+         ReadStorage<'a, TeleportsVictim>,
+         ReadStorage<'a, Alarm>,
+         ReadStorage<'a, SingleActivation>),
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (entities, mut map, player_entity, mut player_pos, mut rng, mut events, mut entity_moved, mut positions, mut viewsheds,
+            mut hidden, mut inflict_damage, mut alerted,
+            (entry_triggers, names, monsters, blockers, damages, teleports, alarms, single_activations)) = data;
+
+        let mut spent_traps: Vec<Entity> = Vec::new();
+        let mut to_teleport: Vec<Entity> = Vec::new();
+        let mut alarms_raised: Vec<(Point, i32)> = Vec::new();
+
+        for (entity, _moved, pos) in (&entities, &entity_moved, &positions).join() {
+            let idx = map.xy_idx(pos.x, pos.y);
+            for trap in map.tile_content[idx].iter() {
+                if *trap == entity || entry_triggers.get(*trap).is_none() { continue; }
+
+                // a sprung trap is no secret any more
+                hidden.remove(*trap);
+
+                let trap_name = names.get(*trap).map(|n| n.name.clone()).unwrap_or_else(|| "a trap".to_string());
+                let victim_name = names.get(entity).map(|n| n.name.clone()).unwrap_or_default();
+                events.emit(GameEvent::TrapTriggered { trap: *trap, trap_name: trap_name.clone(), victim: entity, victim_name, seen: map.visible_tiles[idx] });
+
+                if let Some(damage) = damages.get(*trap) {
+                    SufferDamage::new_damage(&mut inflict_damage, entity, damage.damage, None);
+                    let target_name = names.get(entity).map(|n| n.name.clone()).unwrap_or_default();
+                    events.emit(GameEvent::DamageDealt { source: None, source_name: trap_name.clone(), target: entity, target_name, amount: damage.damage, kind: DamageKind::Trap });
+                }
+                if teleports.get(*trap).is_some() {
+                    to_teleport.push(entity);
+                }
+                if let Some(alarm) = alarms.get(*trap) {
+                    alarms_raised.push((Point::new(pos.x, pos.y), alarm.radius));
+                }
+                if single_activations.get(*trap).is_some() {
+                    spent_traps.push(*trap);
+                }
+            }
+        }
+        entity_moved.clear();
+
+        sort_by_position(&mut to_teleport, &positions);
+        for victim in to_teleport.iter() {
+            // give up rather than spin forever on a map with nowhere to go
+            for _try in 0..100 {
+                let idx = rng.range(0, map.tiles.len() as i32) as usize;
+                if map.tiles[idx] != TileType::Floor || map.blocked[idx] { continue; }
+
+                let pos = positions.get_mut(*victim).unwrap();
+                let old_idx = map.xy_idx(pos.x, pos.y);
+                if blockers.get(*victim).is_some() {
+                    map.blocked[old_idx] = false;
+                    map.blocked[idx] = true;
+                }
+                pos.x = idx as i32 % map.width;
+                pos.y = idx as i32 / map.width;
+                if let Some(viewshed) = viewsheds.get_mut(*victim) {
+                    viewshed.dirty = true;
+                }
+                if *victim == *player_entity {
+                    player_pos.x = pos.x;
+                    player_pos.y = pos.y;
+                }
+                events.emit(GameEvent::Teleported { entity: *victim });
+                break;
+            }
+        }
+
+        for (origin, radius) in alarms_raised.iter() {
+            events.emit(GameEvent::AlarmRaised { x: origin.x, y: origin.y });
+            for (monster_entity, _monster, pos) in (&entities, &monsters, &positions).join() {
+                let distance = rltk::DistanceAlg::Pythagoras.distance2d(*origin, Point::new(pos.x, pos.y));
+                if distance <= *radius as f32 {
+                    alerted.insert(monster_entity, Alerted { x: origin.x, y: origin.y }).expect("Unable to alert monster");
+                }
+            }
+        }
+
+        for trap in spent_traps.iter() {
+            entities.delete(*trap).expect("Unable to delete trap");
+        }
+    }
+}
+
+/// Gives the player a roll each turn to notice hidden things close by.
+pub struct SearchSystem {}
+
+impl<'a> System<'a> for SearchSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        Entities<'a>,
+        ReadExpect<'a, Entity>,
+        ReadExpect<'a, RunState>,
+        WriteExpect<'a, RandomNumberGenerator>,
+        WriteExpect<'a, EventQueue>,
+        ReadStorage<'a, Viewshed>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, Attributes>,
+        ReadStorage<'a, Name>,
+        WriteStorage<'a, Hidden>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (entities, player_entity, runstate, mut rng, mut events, viewsheds, positions, attributes, names, mut hidden) = data;
+
+        if *runstate != RunState::PlayerTurn {return;}
+
+        let (viewshed, player_pos) = match (viewsheds.get(*player_entity), positions.get(*player_entity)) {
+            (Some(v), Some(p)) => (v, Point::new(p.x, p.y)),
+            _ => return
+        };
+        let search_bonus = attributes.get(*player_entity).map(|a| gamesystem::attr_bonus(a.intelligence)).unwrap_or(0);
+
+        let mut nearby: Vec<Entity> = (&entities, &hidden, &positions).join().map(|(entity, _hidden, _pos)| entity).collect();
+        sort_by_position(&mut nearby, &positions);
+
+        let mut spotted: Vec<Entity> = Vec::new();
+        for entity in nearby {
+            let pos = positions.get(entity).unwrap();
+            let pos = Point::new(pos.x, pos.y);
+            if !viewshed.visible_tiles.contains(&pos) { continue; }
+            if rltk::DistanceAlg::Pythagoras.distance2d(player_pos, pos) > SEARCH_RADIUS { continue; }
+
+            if rng.roll_dice(1, 20) + search_bonus >= SEARCH_DIFFICULTY {
+                spotted.push(entity);
+                if let Some(name) = names.get(entity) {
+                    events.emit(GameEvent::Spotted { entity, name: name.name.clone() });
+                }
+            }
+        }
+
+        for entity in spotted.iter() {
+            hidden.remove(*entity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{State, MapIndexingSystem, raws};
+
+    /// Puts `entity` on `(x, y)` as if it had just walked there and lets the traps react.
+    fn walk_onto(gs: &mut State, entity: Entity, x: i32, y: i32) {
+        {
+            let mut positions = gs.ecs.write_storage::<Position>();
+            let pos = positions.get_mut(entity).unwrap();
+            pos.x = x;
+            pos.y = y;
+        }
+        gs.ecs.write_storage::<EntityMoved>().insert(entity, EntityMoved{}).unwrap();
+        MapIndexingSystem{}.run_now(&gs.ecs);
+        TriggerSystem{}.run_now(&gs.ecs);
+        gs.ecs.maintain();
+    }
+
+    fn damage_to(gs: &State, entity: Entity) -> Vec<(i32, Option<Entity>)> {
+        gs.ecs.read_storage::<SufferDamage>().get(entity).map(|d| d.amount.clone()).unwrap_or_default()
+    }
+
+    #[test]
+    fn a_bear_trap_bites_once() {
+        let mut gs = State::new_game(1, None, false, raws::load_raws().unwrap());
+        let goblin = raws::spawn_named_entity(&mut gs.ecs, "Goblin", 9, 10).unwrap();
+        let trap = raws::spawn_named_entity(&mut gs.ecs, "Bear Trap", 10, 10).unwrap();
+        gs.ecs.write_resource::<EventQueue>().clear();
+
+        walk_onto(&mut gs, goblin, 10, 10);
+        assert_eq!(damage_to(&gs, goblin), vec![(6, None)]);
+        assert!(!gs.ecs.is_alive(trap), "a bear trap only goes off once");
+        assert!(gs.ecs.fetch::<EventQueue>().iter().any(|event| matches!(event,
+            GameEvent::TrapTriggered { trap: t, victim, .. } if *t == trap && *victim == goblin)));
+
+        walk_onto(&mut gs, goblin, 9, 10);
+        walk_onto(&mut gs, goblin, 10, 10);
+        assert_eq!(damage_to(&gs, goblin).len(), 1);
+    }
+
+    #[test]
+    fn standing_still_on_a_trap_does_nothing() {
+        let mut gs = State::new_game(1, None, false, raws::load_raws().unwrap());
+        let goblin = raws::spawn_named_entity(&mut gs.ecs, "Goblin", 10, 10).unwrap();
+        let trap = raws::spawn_named_entity(&mut gs.ecs, "Bear Trap", 10, 10).unwrap();
+        MapIndexingSystem{}.run_now(&gs.ecs);
+        TriggerSystem{}.run_now(&gs.ecs);
+        assert!(damage_to(&gs, goblin).is_empty());
+        assert!(gs.ecs.read_storage::<Hidden>().get(trap).is_some());
+    }
+
+    #[test]
+    fn an_alarm_wakes_the_monsters_in_earshot() {
+        let mut gs = State::new_game(1, None, false, raws::load_raws().unwrap());
+        let player = *gs.ecs.fetch::<Entity>();
+        raws::spawn_named_entity(&mut gs.ecs, "Alarm Trap", 10, 10).unwrap();
+        let near = raws::spawn_named_entity(&mut gs.ecs, "Goblin", 20, 15).unwrap();
+        let far = raws::spawn_named_entity(&mut gs.ecs, "Goblin", 60, 10).unwrap();
+
+        walk_onto(&mut gs, player, 10, 10);
+        let alerted = gs.ecs.read_storage::<Alerted>();
+        let alert = alerted.get(near).unwrap();
+        assert_eq!((alert.x, alert.y), (10, 10));
+        assert!(alerted.get(far).is_none());
+    }
+
+    #[test]
+    fn searching_spots_only_what_the_player_can_see() {
+        let mut gs = State::new_game(1, None, false, raws::load_raws().unwrap());
+        let player = *gs.ecs.fetch::<Entity>();
+        let player_pos = *gs.ecs.fetch::<Point>();
+        let seen = raws::spawn_named_entity(&mut gs.ecs, "Bear Trap", player_pos.x + 1, player_pos.y).unwrap();
+        let unseen = raws::spawn_named_entity(&mut gs.ecs, "Bear Trap", player_pos.x, player_pos.y + 1).unwrap();
+        gs.ecs.write_storage::<Viewshed>().get_mut(player).unwrap().visible_tiles = vec![Point::new(player_pos.x + 1, player_pos.y)];
+        *gs.ecs.write_resource::<RunState>() = RunState::PlayerTurn;
+
+        for _ in 0..100 {
+            SearchSystem{}.run_now(&gs.ecs);
+        }
+        let hidden = gs.ecs.read_storage::<Hidden>();
+        assert!(hidden.get(seen).is_none(), "a hundred rolls and still not spotted");
+        assert!(hidden.get(unseen).is_some());
+        assert!(gs.ecs.fetch::<EventQueue>().iter().any(|event| matches!(event, GameEvent::Spotted { entity, .. } if *entity == seen)));
+    }
+}
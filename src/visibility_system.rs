@@ -1,5 +1,5 @@
 use specs::prelude::*;
-use super::{Viewshed, Position, Map, Player};
+use super::{Viewshed, Position, Map, Player, BlocksVisibility};
 use rltk::{field_of_view, Point};
 
 pub struct VisibilitySystem {}
@@ -9,10 +9,17 @@ impl<'a> System<'a> for VisibilitySystem {
                         Entities<'a>,
                         WriteStorage<'a, Viewshed>,
                         WriteStorage<'a, Position>,
-                        ReadStorage<'a, Player>);
+                        ReadStorage<'a, Player>,
+                        ReadStorage<'a, BlocksVisibility>);
 
     fn run(&mut self, data : Self::SystemData) {
-        let (mut map, entities, mut viewshed, pos, player) = data;
+        let (mut map, entities, mut viewshed, pos, player, blocks_visibility) = data;
+
+        for v in map.view_blocked.iter_mut() { *v = false; }
+        for (block_pos, _block) in (&pos, &blocks_visibility).join() {
+            let idx = map.xy_idx(block_pos.x, block_pos.y);
+            map.view_blocked[idx] = true;
+        }
 
         for (ent,viewshed,pos) in (&entities, &mut viewshed, &pos).join() {
             if viewshed.dirty {